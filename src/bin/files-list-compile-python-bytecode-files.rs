@@ -0,0 +1,35 @@
+//! Executable for the fileslist compile-python-bytecode-files command
+use std::process::ExitCode;
+
+use wheelhoss::cli::{Command, CommandOption};
+
+const COMMAND: Command = Command {
+    adds: "Python bytecode files",
+    removes: false,
+    read_only: false,
+    options: &[CommandOption {
+        name: "--interpreter",
+        value: Some("interpreter_path"),
+        required: true,
+        help: "Python 3 beneath root_path, e.g. \"opt/app-venv/bin/python\"",
+    }],
+};
+
+fn main() -> ExitCode {
+    COMMAND.main(|files_list, options| {
+        let interpreter = options.value("--interpreter").unwrap_or_default();
+        // Nothing is compiled, and so written beneath the root, unless the files list is written.
+        let bytecode_files = if options.dry_run || options.check {
+            files_list.add_python_bytecode_files_to_compile(interpreter)?
+        } else {
+            files_list.compile_python_bytecode_files(interpreter)?
+        };
+        for (source, message) in bytecode_files.compile_errors.iter() {
+            eprintln!("Failed to compile {}: {}", source, message);
+        }
+        for source in bytecode_files.without_bytecode.iter() {
+            eprintln!("No Python bytecode for {}", source);
+        }
+        Ok(bytecode_files.added)
+    })
+}
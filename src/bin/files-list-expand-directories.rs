@@ -0,0 +1,37 @@
+//! Executable for the fileslist expand-directories command
+use std::process::ExitCode;
+
+use wheelhoss::cli::{Command, CommandOption};
+use wheelhoss::error::Error;
+
+const COMMAND: Command = Command {
+    adds: "files beneath listed directories",
+    removes: false,
+    read_only: false,
+    options: &[CommandOption {
+        name: "--min-untracked",
+        value: Some("count"),
+        required: false,
+        help: "expand only directories with at least count files which are not listed; default: 1",
+    }],
+};
+
+fn main() -> ExitCode {
+    COMMAND.main(|files_list, options| {
+        let min_untracked = match options.value("--min-untracked") {
+            Some(count) => count.parse().map_err(|_| {
+                Error::InvalidOptionValue("--min-untracked".to_string(), count.to_string())
+            })?,
+            None => 1,
+        };
+        for directory in files_list.listed_directories()? {
+            if directory.is_large() {
+                eprintln!(
+                    "Warning: {} includes {} files which are not listed",
+                    directory.entry, directory.untracked
+                );
+            }
+        }
+        files_list.expand_directories(min_untracked)
+    })
+}
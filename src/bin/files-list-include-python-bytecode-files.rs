@@ -0,0 +1,26 @@
+//! Executable for the fileslist include-python-bytecode-files command
+use std::process::ExitCode;
+
+use wheelhoss::cli::{Command, CommandOption};
+
+const COMMAND: Command = Command {
+    adds: "Python bytecode files",
+    removes: false,
+    read_only: false,
+    options: &[CommandOption {
+        name: "--cache-tag",
+        value: Some("cache_tag"),
+        required: false,
+        help: "default: detected from the files list, e.g. \"cpython-39\"",
+    }],
+};
+
+fn main() -> ExitCode {
+    COMMAND.main(|files_list, options| {
+        let bytecode_files = files_list.add_python_bytecode_files(options.value("--cache-tag"))?;
+        for source in bytecode_files.without_bytecode.iter() {
+            eprintln!("No Python bytecode for {}", source);
+        }
+        Ok(bytecode_files.added)
+    })
+}
@@ -0,0 +1,22 @@
+//! Executable for the fileslist include-python-distribution-files command
+use std::process::ExitCode;
+
+use wheelhoss::cli::{Command, CommandOption};
+
+const COMMAND: Command = Command {
+    adds: "Python distribution files",
+    removes: false,
+    read_only: false,
+    options: &[CommandOption {
+        name: "--exclude",
+        value: Some("pattern"),
+        required: false,
+        help: "skip files matching the glob, e.g. \"**/tests/**\"; may be repeated",
+    }],
+};
+
+fn main() -> ExitCode {
+    COMMAND.main(|files_list, options| {
+        files_list.add_python_distribution_files(options.all_values("--exclude"))
+    })
+}
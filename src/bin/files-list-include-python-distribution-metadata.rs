@@ -0,0 +1,15 @@
+//! Executable for the fileslist include-python-distribution-metadata command
+use std::process::ExitCode;
+
+use wheelhoss::cli::Command;
+
+const COMMAND: Command = Command {
+    adds: "Python distribution metadata files",
+    removes: false,
+    read_only: false,
+    options: &[],
+};
+
+fn main() -> ExitCode {
+    COMMAND.main(|files_list, _| files_list.add_python_distribution_metadata())
+}
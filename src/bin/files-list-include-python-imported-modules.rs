@@ -0,0 +1,15 @@
+//! Executable for the fileslist include-python-imported-modules command
+use std::process::ExitCode;
+
+use wheelhoss::cli::Command;
+
+const COMMAND: Command = Command {
+    adds: "imported Python modules",
+    removes: false,
+    read_only: false,
+    options: &[],
+};
+
+fn main() -> ExitCode {
+    COMMAND.main(|files_list, _| files_list.add_python_imported_modules())
+}
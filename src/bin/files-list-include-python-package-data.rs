@@ -0,0 +1,28 @@
+//! Executable for the fileslist include-python-package-data command
+use std::process::ExitCode;
+
+use wheelhoss::cli::{Command, CommandOption};
+use wheelhoss::files_list::DEFAULT_PACKAGE_DATA_PATTERNS;
+
+const COMMAND: Command = Command {
+    adds: "Python package data files",
+    removes: false,
+    read_only: false,
+    options: &[CommandOption {
+        name: "--pattern",
+        value: Some("pattern"),
+        required: false,
+        help: "default: \"templates/**\", \"locale/**/*.mo\" and \"static/**\"; may be repeated",
+    }],
+};
+
+fn main() -> ExitCode {
+    COMMAND.main(|files_list, options| {
+        let patterns = options.all_values("--pattern");
+        if patterns.is_empty() {
+            files_list.add_python_package_data(&DEFAULT_PACKAGE_DATA_PATTERNS)
+        } else {
+            files_list.add_python_package_data(patterns)
+        }
+    })
+}
@@ -1,52 +1,31 @@
 //! Executable for the fileslist include-python-source-files command
-use std::env;
-use std::path::Path;
-use std::process;
+use std::process::ExitCode;
 
-use wheelhoss::error::Error;
-use wheelhoss::files_list::FilesList;
+use wheelhoss::cli::{Command, CommandOption};
 
-const DEFAULT_FILES_LIST_PATH: &str = "./.sandstorm/sandstorm-files.list";
+const COMMAND: Command = Command {
+    adds: "Python source files",
+    removes: false,
+    read_only: false,
+    options: &[CommandOption {
+        name: "--optimization-levels",
+        value: None,
+        required: false,
+        help: "report the optimization levels of the listed bytecode files",
+    }],
+};
 
-fn main() -> Result<(), Error> {
-    let mut args = env::args();
-    if args.len() < 3 {
-        let arg_path = match args.nth(1) {
-            Some(path) => path,
-            None => DEFAULT_FILES_LIST_PATH.to_string(),
-        };
-        let path = Path::new(&arg_path);
-        let mut files_list = FilesList::new(path);
-        match files_list.include_python_source_files() {
-            Ok(included) => {
-                println!("{:?}", included);
-            }
-            Err(e) => {
-                eprintln!("{}", e);
-                process::exit(2);
+fn main() -> ExitCode {
+    COMMAND.main(|files_list, options| {
+        if options.is_set("--optimization-levels") {
+            for (optimization, count) in files_list.optimization_levels() {
+                let level = match optimization {
+                    Some(optimization) => format!("opt-{}", optimization),
+                    None => "none".to_string(),
+                };
+                eprintln!("{} bytecode files at optimization level {}", count, level);
             }
         }
-    } else {
-        usage()?;
-        process::exit(1);
-    }
-    Ok(())
-}
-
-fn usage() -> Result<(), Error> {
-    let current_exe_pathbuf = env::current_exe()?;
-    let file_name = match current_exe_pathbuf.file_name() {
-        Some(file_name) => match file_name.to_str() {
-            Some(name) => name,
-            None => env!("CARGO_BIN_NAME"),
-        },
-        None => env!("CARGO_BIN_NAME"),
-    };
-    println!("{} [files_list_path]", file_name);
-    println!();
-    println!(
-        "\tfiles_list_path\t\tdefault: \"{}\"",
-        DEFAULT_FILES_LIST_PATH
-    );
-    Ok(())
+        files_list.add_python_source_files()
+    })
 }
@@ -0,0 +1,23 @@
+//! Executable for the fileslist include-shared-libraries command
+use std::process::ExitCode;
+
+use wheelhoss::cli::Command;
+
+const COMMAND: Command = Command {
+    adds: "shared libraries",
+    removes: false,
+    read_only: false,
+    options: &[],
+};
+
+fn main() -> ExitCode {
+    COMMAND.main(|files_list, _| {
+        let shared_libraries = files_list.add_shared_libraries()?;
+        for (object, libraries) in shared_libraries.missing.iter() {
+            for library in libraries.iter() {
+                eprintln!("No {} for {}", library, object);
+            }
+        }
+        Ok(shared_libraries.added)
+    })
+}
@@ -0,0 +1,15 @@
+//! Executable for the fileslist include-symlink-targets command
+use std::process::ExitCode;
+
+use wheelhoss::cli::Command;
+
+const COMMAND: Command = Command {
+    adds: "symbolic links and their targets",
+    removes: false,
+    read_only: false,
+    options: &[],
+};
+
+fn main() -> ExitCode {
+    COMMAND.main(|files_list, _| files_list.add_symlink_targets())
+}
@@ -0,0 +1,40 @@
+//! Executable for the fileslist move-to-always-include command
+use std::process::ExitCode;
+
+use wheelhoss::cli::{Command, CommandOption};
+use wheelhoss::diff::unified_diff;
+use wheelhoss::pkgdef::Pkgdef;
+
+const COMMAND: Command = Command {
+    adds: "entries beneath the prefixes",
+    removes: true,
+    read_only: false,
+    options: &[CommandOption {
+        name: "--prefix",
+        value: Some("prefix"),
+        required: true,
+        help: "e.g. \"opt/app\"; may be repeated",
+    }],
+};
+
+fn main() -> ExitCode {
+    // The package definition is written before the files list file, so that the entries are in
+    // one of the files even if writing the files list file fails.
+    COMMAND.main(|files_list, options| {
+        let pkgdef_path = options.pkgdef_path();
+        let mut pkgdef = Pkgdef::read(&pkgdef_path)?;
+        let before = pkgdef.to_string();
+        let removed =
+            files_list.move_to_always_include(&mut pkgdef, options.all_values("--prefix"));
+        if options.dry_run {
+            let name = pkgdef_path.to_string_lossy();
+            let after = pkgdef.to_string();
+            let before: Vec<&str> = before.lines().collect();
+            let after: Vec<&str> = after.lines().collect();
+            print!("{}", unified_diff(&name, &name, &before, &after));
+        } else if !options.check {
+            pkgdef.write(&pkgdef_path)?;
+        }
+        Ok(removed)
+    })
+}
@@ -0,0 +1,69 @@
+//! Executable for the fileslist pack command
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use wheelhoss::cli::{Command, CommandOption};
+use wheelhoss::error::Error;
+use wheelhoss::pkgdef::Pkgdef;
+use wheelhoss::spk::{self, KEYRING_FILE_NAME};
+
+const COMMAND: Command = Command {
+    adds: "package files",
+    removes: false,
+    read_only: true,
+    options: &[
+        CommandOption {
+            name: "--output",
+            value: Some("spk_path"),
+            required: true,
+            help: "the package to write, e.g. \"app.spk\"",
+        },
+        CommandOption {
+            name: "--keyring",
+            value: Some("keyring_path"),
+            required: false,
+            help: "the keyring of `spk keygen`; default: \"~/.sandstorm-keyring\"",
+        },
+    ],
+};
+
+fn main() -> ExitCode {
+    COMMAND.main(|files_list, options| {
+        let pkgdef_path = options.pkgdef_path();
+        let pkgdef = Pkgdef::read(&pkgdef_path)?;
+        let app_id = pkgdef.id().ok_or_else(|| {
+            Error::InvalidPkgdef(format!("{} has no id", pkgdef_path.to_string_lossy()))
+        })?;
+        let spk_path = Path::new(options.value("--output").unwrap_or_default());
+
+        let contents = if options.dry_run || options.check {
+            files_list.pack_contents(&pkgdef, &options.pkgdef_directory())?
+        } else {
+            // The keyring is only read to write the package.
+            let keyring_path = match options.value("--keyring") {
+                Some(keyring_path) => PathBuf::from(keyring_path),
+                None => match std::env::var_os("HOME") {
+                    Some(home) => PathBuf::from(home).join(KEYRING_FILE_NAME),
+                    None => PathBuf::from(KEYRING_FILE_NAME),
+                },
+            };
+            let signing_key = spk::signing_key(&keyring_path, app_id)?;
+            files_list.pack(&pkgdef, &options.pkgdef_directory(), &signing_key, spk_path)?
+        };
+        if options.dry_run {
+            let paths: BTreeSet<&String> = contents
+                .files
+                .keys()
+                .chain(contents.directories.iter())
+                .chain(contents.symlinks.keys())
+                .collect();
+            for path in paths {
+                println!("{}", path);
+            }
+        } else if !options.check {
+            eprintln!("Wrote {} for app ID {}", spk_path.to_string_lossy(), app_id);
+        }
+        Ok(BTreeSet::new())
+    })
+}
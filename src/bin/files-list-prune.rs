@@ -0,0 +1,15 @@
+//! Executable for the fileslist prune command
+use std::process::ExitCode;
+
+use wheelhoss::cli::Command;
+
+const COMMAND: Command = Command {
+    adds: "entries which do not exist",
+    removes: true,
+    read_only: false,
+    options: &[],
+};
+
+fn main() -> ExitCode {
+    COMMAND.main(|files_list, _| files_list.prune())
+}
@@ -0,0 +1,538 @@
+//! Command line interface
+//!
+//! `cli` holds the argument handling and the dry run, check and write modes shared by the
+//! `wheelhoss-files-list-*` executables.
+use std::collections::{BTreeMap, BTreeSet};
+use std::env;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use crate::diff::unified_diff;
+use crate::error::Error;
+use crate::files_list::FilesList;
+use crate::pkgdef::{Pkgdef, PKGDEF_FILE_NAME};
+
+pub const DEFAULT_FILES_LIST_PATH: &str = "./.sandstorm/sandstorm-files.list";
+pub const DEFAULT_ROOT_PATH: &str = "/";
+
+/// Exit status for success.
+pub const EXIT_SUCCESS: u8 = 0;
+/// Exit status for incorrect arguments.
+pub const EXIT_USAGE: u8 = 1;
+/// Exit status for an `Error`.
+pub const EXIT_ERROR: u8 = 2;
+/// Exit status for `--check` when the files list is incomplete.
+pub const EXIT_CHECK_FAILED: u8 = 3;
+
+/// An option specific to one executable.
+pub struct CommandOption {
+    /// The name of the option, such as `--cache-tag`.
+    pub name: &'static str,
+    /// The name of the option's value, such as `cache_tag`, or `None` for a flag.
+    pub value: Option<&'static str>,
+    /// `true` if the option must be given.
+    pub required: bool,
+    /// A short description of the option for the usage message.
+    pub help: &'static str,
+}
+
+/// A `wheelhoss-files-list-*` executable.
+pub struct Command {
+    /// What the command adds to the files list, such as "Python source files".
+    pub adds: &'static str,
+    /// `true` if the command removes what `adds` describes from the files list instead.
+    pub removes: bool,
+    /// `true` if the command only reads the files list, which is then neither created nor saved.
+    pub read_only: bool,
+    /// The options specific to the command.
+    pub options: &'static [CommandOption],
+}
+
+/// The parsed command line arguments.
+pub struct Options {
+    pub files_list_path: PathBuf,
+    pub root: PathBuf,
+    /// The package definition file given with `--pkgdef`.
+    pub pkgdef: Option<PathBuf>,
+    pub dry_run: bool,
+    pub check: bool,
+    pub implementation_tags: Vec<String>,
+    /// The values of the command's own options, by name.  Flags have an empty value.
+    pub values: BTreeMap<&'static str, Vec<String>>,
+}
+
+impl Options {
+    /// Returns the path of the package definition file: the one given with `--pkgdef`, or
+    /// `sandstorm-pkgdef.capnp` beside the files list file.
+    pub fn pkgdef_path(&self) -> PathBuf {
+        match &self.pkgdef {
+            Some(pkgdef) => pkgdef.clone(),
+            None => self.files_list_path.with_file_name(PKGDEF_FILE_NAME),
+        }
+    }
+
+    /// Returns the directory of the package definition file, against which its relative source
+    /// paths are resolved.
+    pub fn pkgdef_directory(&self) -> PathBuf {
+        match self.pkgdef_path().parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+            _ => PathBuf::from("."),
+        }
+    }
+
+    /// Returns the last value given for the command's option `name`.
+    pub fn value(&self, name: &str) -> Option<&str> {
+        self.values
+            .get(name)
+            .and_then(|values| values.last())
+            .map(String::as_str)
+    }
+
+    /// Returns every value given for the command's option `name`, in order.
+    pub fn all_values(&self, name: &str) -> &[String] {
+        self.values.get(name).map_or(&[], Vec::as_slice)
+    }
+
+    /// Returns `true` if the command's option `name` was given.
+    pub fn is_set(&self, name: &str) -> bool {
+        self.values.contains_key(name)
+    }
+}
+
+impl Command {
+    /// Parses the command line arguments and runs the command.
+    ///
+    /// `add` adds entries to the loaded files list and returns the added entries, or removes them if
+    /// the command `removes`.  With `--dry-run`, the changes are printed as a unified diff.  With
+    /// `--check`, the entries which would be changed are printed and `EXIT_CHECK_FAILED` is
+    /// returned if there are any.  Otherwise, the files list file is saved, unless the command is
+    /// `read_only`, and the changed entries are printed.
+    ///
+    /// Returns the exit status for the `main` function of the executable: `EXIT_USAGE` after
+    /// printing the usage message for incorrect arguments and `EXIT_ERROR` after printing an
+    /// `Error`.
+    pub fn main<F>(&self, add: F) -> ExitCode
+    where
+        F: FnOnce(&mut FilesList, &Options) -> Result<BTreeSet<String>, Error>,
+    {
+        let result = match self.parse_args(env::args().skip(1)) {
+            Some(options) => self.run(&options, add),
+            None => self.usage().map(|_| EXIT_USAGE),
+        };
+        Command::exit_code(result)
+    }
+
+    /// Parses the command line arguments and runs the command as `main` does, except that `add`
+    /// returns a function along with the changed entries, which is called after the files list
+    /// file is saved.
+    ///
+    /// A command which also writes another file, such as the package definition, writes it in
+    /// that function, so that it is written only once the files list file is.  The function is
+    /// not called with `--dry-run` or `--check`.
+    pub fn main_and_then<F, G>(&self, add: F) -> ExitCode
+    where
+        F: FnOnce(&mut FilesList, &Options) -> Result<(BTreeSet<String>, G), Error>,
+        G: FnOnce() -> Result<(), Error>,
+    {
+        let result = match self.parse_args(env::args().skip(1)) {
+            Some(options) => self.run_and_then(&options, add),
+            None => self.usage().map(|_| EXIT_USAGE),
+        };
+        Command::exit_code(result)
+    }
+
+    /// Returns the exit code for `result`, after printing its `Error`.
+    fn exit_code(result: Result<u8, Error>) -> ExitCode {
+        match result {
+            Ok(status) => ExitCode::from(status),
+            Err(e) => {
+                eprintln!("{}", e);
+                ExitCode::from(EXIT_ERROR)
+            }
+        }
+    }
+
+    /// Runs the command with `options` and returns its exit status.
+    fn run<F>(&self, options: &Options, add: F) -> Result<u8, Error>
+    where
+        F: FnOnce(&mut FilesList, &Options) -> Result<BTreeSet<String>, Error>,
+    {
+        self.run_and_then(options, |files_list, options| {
+            Ok((add(files_list, options)?, || Ok(())))
+        })
+    }
+
+    /// Runs the command with `options`, as `main_and_then` describes, and returns its exit
+    /// status.
+    fn run_and_then<F, G>(&self, options: &Options, add: F) -> Result<u8, Error>
+    where
+        F: FnOnce(&mut FilesList, &Options) -> Result<(BTreeSet<String>, G), Error>,
+        G: FnOnce() -> Result<(), Error>,
+    {
+        let name = options.files_list_path.to_string_lossy();
+        let mut files_list =
+            FilesList::with_root(options.files_list_path.as_path(), options.root.as_path());
+        if !options.implementation_tags.is_empty() {
+            files_list.set_implementation_tags(&options.implementation_tags);
+        }
+        let pkgdef_path = options.pkgdef_path();
+        if options.pkgdef.is_some() || pkgdef_path.is_file() {
+            let pkgdef = Pkgdef::read(&pkgdef_path)?;
+            files_list.set_source_map(pkgdef.source_map, &options.pkgdef_directory());
+        }
+        if options.dry_run || options.check || self.read_only {
+            files_list.set_create(false);
+        }
+        files_list.load()?;
+        // The diff is against the file as it is, which `save` would also sort and normalize.
+        let before: Vec<String> = if options.dry_run && !self.read_only {
+            std::fs::read_to_string(&options.files_list_path)?
+                .lines()
+                .map(String::from)
+                .collect()
+        } else {
+            Vec::new()
+        };
+        let (added, and_then) = add(&mut files_list, options)?;
+        for entry in files_list.unreachable_entries().iter() {
+            eprintln!(
+                "Skipped {}, which the source map of \"{}\" hides or does not find",
+                entry,
+                pkgdef_path.to_string_lossy()
+            );
+        }
+        if options.dry_run {
+            if !self.read_only {
+                let after: Vec<String> = files_list.lines().map(String::from).collect();
+                print!("{}", unified_diff(&name, &name, &before, &after));
+            }
+        } else if options.check {
+            for entry in added.iter() {
+                println!("{}", entry);
+            }
+            if !added.is_empty() {
+                eprintln!(
+                    "{} {} are {} \"{}\"",
+                    added.len(),
+                    self.adds,
+                    self.check_failure(),
+                    name
+                );
+                return Ok(EXIT_CHECK_FAILED);
+            }
+        } else {
+            if !self.read_only {
+                files_list.save()?;
+            }
+            for entry in added.iter() {
+                println!("{}", entry);
+            }
+            and_then()?;
+        }
+        Ok(EXIT_SUCCESS)
+    }
+
+    /// Parses `args`, returning `None` if they are incorrect.
+    pub fn parse_args<I: Iterator<Item = String>>(&self, mut args: I) -> Option<Options> {
+        let mut files_list_path: Option<String> = None;
+        let mut root_path: Option<String> = None;
+        let mut options = Options {
+            files_list_path: PathBuf::new(),
+            root: PathBuf::new(),
+            pkgdef: None,
+            dry_run: false,
+            check: false,
+            implementation_tags: Vec::new(),
+            values: BTreeMap::new(),
+        };
+
+        while let Some(arg) = args.next() {
+            if arg == "--root" && root_path.is_none() {
+                root_path = Some(args.next()?);
+            } else if arg == "--pkgdef" && options.pkgdef.is_none() {
+                options.pkgdef = Some(PathBuf::from(args.next()?));
+            } else if arg == "--implementation-tag" {
+                options.implementation_tags.push(args.next()?);
+            } else if arg == "--dry-run" && !options.check {
+                options.dry_run = true;
+            } else if arg == "--check" && !options.dry_run {
+                options.check = true;
+            } else if let Some(option) = self.options.iter().find(|option| option.name == arg) {
+                let value = match option.value {
+                    Some(_) => args.next()?,
+                    None => String::new(),
+                };
+                options.values.entry(option.name).or_default().push(value);
+            } else if !arg.starts_with("--") && files_list_path.is_none() {
+                files_list_path = Some(arg);
+            } else {
+                return None;
+            }
+        }
+
+        if self
+            .options
+            .iter()
+            .any(|option| option.required && !options.is_set(option.name))
+        {
+            return None;
+        }
+        options.files_list_path =
+            PathBuf::from(files_list_path.unwrap_or_else(|| DEFAULT_FILES_LIST_PATH.to_string()));
+        options.root = PathBuf::from(root_path.unwrap_or_else(|| DEFAULT_ROOT_PATH.to_string()));
+        Some(options)
+    }
+
+    fn usage(&self) -> Result<(), Error> {
+        let current_exe_pathbuf = env::current_exe()?;
+        let file_name = match current_exe_pathbuf.file_name() {
+            Some(file_name) => match file_name.to_str() {
+                Some(name) => name.to_string(),
+                None => "wheelhoss".to_string(),
+            },
+            None => "wheelhoss".to_string(),
+        };
+        let mut synopsis = format!(
+            "{} [--dry-run | --check] [--root root_path] [--pkgdef pkgdef_path] \
+             [--implementation-tag tag]...",
+            file_name
+        );
+        for option in self.options.iter() {
+            let usage = match option.value {
+                Some(value) => format!("{} {}", option.name, value),
+                None => option.name.to_string(),
+            };
+            if option.required {
+                synopsis.push_str(&format!(" {}", usage));
+            } else {
+                synopsis.push_str(&format!(" [{}]", usage));
+            }
+        }
+        println!("{} [files_list_path]", synopsis);
+        println!();
+        println!(
+            "\tfiles_list_path\t\tdefault: \"{}\"",
+            DEFAULT_FILES_LIST_PATH
+        );
+        println!("\t--root root_path\tdefault: \"{}\"", DEFAULT_ROOT_PATH);
+        println!(
+            "\t--pkgdef pkgdef_path\tdefault: \"{}\" beside files_list_path, if present",
+            PKGDEF_FILE_NAME
+        );
+        println!("\t--implementation-tag tag\tdefault: any, e.g. \"cpython\" or \"pypy\"");
+        println!("\t--dry-run\t\tprint a unified diff instead of writing files_list_path");
+        println!(
+            "\t--check\t\t\texit with status {} if {} are {} files_list_path",
+            EXIT_CHECK_FAILED,
+            self.adds,
+            self.check_failure()
+        );
+        for option in self.options.iter() {
+            match option.value {
+                Some(value) => println!("\t{} {}\t{}", option.name, value, option.help),
+                None => println!("\t{}\t\t{}", option.name, option.help),
+            }
+        }
+        Ok(())
+    }
+
+    /// Describes the entries which make `--check` fail.
+    fn check_failure(&self) -> &'static str {
+        if self.removes {
+            "listed in"
+        } else {
+            "missing from"
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::pkgdef::DEFAULT_PKGDEF_PATH;
+
+    const COMMAND: Command = Command {
+        adds: "things",
+        removes: false,
+        read_only: false,
+        options: &[
+            CommandOption {
+                name: "--cache-tag",
+                value: Some("cache_tag"),
+                required: false,
+                help: "the cache tag",
+            },
+            CommandOption {
+                name: "--verbose",
+                value: None,
+                required: false,
+                help: "say more",
+            },
+        ],
+    };
+
+    fn parse(args: &[&str]) -> Option<Options> {
+        COMMAND.parse_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn command_parse_args_defaults() {
+        let options = parse(&[]).unwrap();
+        assert_eq!(
+            PathBuf::from(DEFAULT_FILES_LIST_PATH),
+            options.files_list_path
+        );
+        assert_eq!(PathBuf::from(DEFAULT_ROOT_PATH), options.root);
+        assert_eq!(None, options.pkgdef);
+        assert_eq!(PathBuf::from(DEFAULT_PKGDEF_PATH), options.pkgdef_path());
+        assert_eq!(PathBuf::from("./.sandstorm"), options.pkgdef_directory());
+        assert!(!options.dry_run && !options.check);
+        assert!(options.implementation_tags.is_empty());
+        assert!(options.values.is_empty());
+    }
+
+    #[test]
+    fn command_parse_args_options() {
+        let options = parse(&[
+            "--root",
+            "/srv/rootfs",
+            "--pkgdef",
+            "pkgdef.capnp",
+            "--check",
+            "--implementation-tag",
+            "pypy",
+            "--cache-tag",
+            "pypy39",
+            "--verbose",
+            "files.list",
+        ])
+        .unwrap();
+        assert_eq!(PathBuf::from("files.list"), options.files_list_path);
+        assert_eq!(PathBuf::from("/srv/rootfs"), options.root);
+        assert_eq!(PathBuf::from("pkgdef.capnp"), options.pkgdef_path());
+        assert_eq!(PathBuf::from("."), options.pkgdef_directory());
+        assert!(options.check);
+        assert_eq!(vec!["pypy"], options.implementation_tags);
+        assert_eq!(Some("pypy39"), options.value("--cache-tag"));
+        assert!(options.is_set("--verbose"));
+        assert!(options.all_values("--unknown").is_empty());
+
+        let options = parse(&["--cache-tag", "cpython-39", "--cache-tag", "pypy39"]).unwrap();
+        assert_eq!(["cpython-39", "pypy39"], options.all_values("--cache-tag"));
+        assert_eq!(Some("pypy39"), options.value("--cache-tag"));
+    }
+
+    #[test]
+    fn command_dry_run_does_not_create_files_list() {
+        let directory = tempfile::tempdir().unwrap();
+        let files_list_file = directory.path().join("sandstorm-files.list");
+        let options = parse(&["--dry-run", files_list_file.to_str().unwrap()]).unwrap();
+        assert!(matches!(
+            COMMAND.run(&options, |_, _| Ok(BTreeSet::new())),
+            Err(Error::FailedToOpenFile(_, Some(_)))
+        ));
+        assert!(!files_list_file.exists());
+    }
+
+    #[test]
+    fn command_check_returns_exit_status() {
+        let directory = tempfile::tempdir().unwrap();
+        let files_list_file = directory.path().join("sandstorm-files.list");
+        std::fs::write(&files_list_file, "bin/sh\n").unwrap();
+        let options = parse(&["--check", files_list_file.to_str().unwrap()]).unwrap();
+        assert_eq!(
+            EXIT_SUCCESS,
+            COMMAND.run(&options, |_, _| Ok(BTreeSet::new())).unwrap()
+        );
+        assert_eq!(
+            EXIT_CHECK_FAILED,
+            COMMAND
+                .run(&options, |_, _| Ok(BTreeSet::from([
+                    "/bin/bash".to_string()
+                ])))
+                .unwrap()
+        );
+    }
+
+    fn not_called() -> Result<(), Error> {
+        panic!("called without saving the files list")
+    }
+
+    #[test]
+    fn command_run_and_then_after_saving() {
+        let directory = tempfile::tempdir().unwrap();
+        let files_list_file = directory.path().join("sandstorm-files.list");
+        std::fs::write(&files_list_file, "bin/sh\n").unwrap();
+        let add = |files_list: &mut FilesList, _: &Options| {
+            files_list.insert("bin/bash");
+            let files_list_file = files_list_file.clone();
+            Ok((BTreeSet::from(["/bin/bash".to_string()]), move || {
+                assert_eq!(
+                    "bin/bash\nbin/sh\n",
+                    std::fs::read_to_string(files_list_file).unwrap()
+                );
+                Ok(())
+            }))
+        };
+        let options = parse(&[files_list_file.to_str().unwrap()]).unwrap();
+        assert_eq!(EXIT_SUCCESS, COMMAND.run_and_then(&options, add).unwrap());
+
+        let options = parse(&["--dry-run", files_list_file.to_str().unwrap()]).unwrap();
+        assert_eq!(
+            EXIT_SUCCESS,
+            COMMAND
+                .run_and_then(&options, |_, _| Ok((BTreeSet::new(), not_called)))
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn command_read_only_does_not_write_files_list() {
+        let command = Command {
+            read_only: true,
+            ..COMMAND
+        };
+        let directory = tempfile::tempdir().unwrap();
+        let files_list_file = directory.path().join("sandstorm-files.list");
+        let options = parse(&[files_list_file.to_str().unwrap()]).unwrap();
+        assert!(matches!(
+            command.run(&options, |_, _| Ok(BTreeSet::new())),
+            Err(Error::FailedToOpenFile(_, Some(_)))
+        ));
+        assert!(!files_list_file.exists());
+
+        std::fs::write(&files_list_file, "bin/sh\nbin/bash\n").unwrap();
+        assert_eq!(
+            EXIT_SUCCESS,
+            command.run(&options, |_, _| Ok(BTreeSet::new())).unwrap()
+        );
+        assert_eq!(
+            "bin/sh\nbin/bash\n",
+            std::fs::read_to_string(&files_list_file).unwrap()
+        );
+    }
+
+    #[test]
+    fn command_check_fails_on_missing_files_list() {
+        let directory = tempfile::tempdir().unwrap();
+        let files_list_file = directory.path().join("missing.list");
+        let options = parse(&["--check", files_list_file.to_str().unwrap()]).unwrap();
+        assert!(matches!(
+            COMMAND.run(&options, |_, _| Ok(BTreeSet::new())),
+            Err(Error::FailedToOpenFile(_, Some(_)))
+        ));
+        assert!(!files_list_file.exists());
+    }
+
+    #[test]
+    fn command_parse_args_rejects_incorrect_arguments() {
+        assert!(parse(&["--check", "--dry-run"]).is_none());
+        assert!(parse(&["--root"]).is_none());
+        assert!(parse(&["--pkgdef", "a.capnp", "--pkgdef", "b.capnp"]).is_none());
+        assert!(parse(&["--cache-tag"]).is_none());
+        assert!(parse(&["--unknown"]).is_none());
+        assert!(parse(&["one.list", "two.list"]).is_none());
+    }
+}
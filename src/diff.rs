@@ -0,0 +1,400 @@
+//! Diff
+//!
+//! `diff` compares two sequences of lines and formats the differences as a unified diff
+
+const CONTEXT_LINES: usize = 3;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Edit {
+    Equal(usize, usize),
+    Delete(usize),
+    Insert(usize),
+}
+
+/// Returns a unified diff which transforms `old_lines` into `new_lines`.
+///
+/// `old_name` and `new_name` label the two sides of the diff.  When the lines are equal, the
+/// result is an empty string.
+pub fn unified_diff<S: AsRef<str>>(
+    old_name: &str,
+    new_name: &str,
+    old_lines: &[S],
+    new_lines: &[S],
+) -> String {
+    let old_lines: Vec<&str> = old_lines.iter().map(AsRef::as_ref).collect();
+    let new_lines: Vec<&str> = new_lines.iter().map(AsRef::as_ref).collect();
+    let edits = shortest_edit(&old_lines, &new_lines);
+    let mut result = String::new();
+
+    let changes: Vec<usize> = edits
+        .iter()
+        .enumerate()
+        .filter(|(_, edit)| !matches!(edit, Edit::Equal(_, _)))
+        .map(|(index, _)| index)
+        .collect();
+    if changes.is_empty() {
+        return result;
+    }
+
+    result.push_str(&format!("--- {}\n+++ {}\n", old_name, new_name));
+    let mut hunk_start = changes[0].saturating_sub(CONTEXT_LINES);
+    let mut hunk_end = changes[0] + 1 + CONTEXT_LINES;
+    for &change in changes.iter().skip(1) {
+        if change.saturating_sub(CONTEXT_LINES) <= hunk_end {
+            hunk_end = change + 1 + CONTEXT_LINES;
+        } else {
+            write_hunk(
+                &mut result,
+                &edits,
+                hunk_start,
+                hunk_end,
+                &old_lines,
+                &new_lines,
+            );
+            hunk_start = change.saturating_sub(CONTEXT_LINES);
+            hunk_end = change + 1 + CONTEXT_LINES;
+        }
+    }
+    write_hunk(
+        &mut result,
+        &edits,
+        hunk_start,
+        hunk_end,
+        &old_lines,
+        &new_lines,
+    );
+    result
+}
+
+fn write_hunk(
+    result: &mut String,
+    edits: &[Edit],
+    start: usize,
+    end: usize,
+    old_lines: &[&str],
+    new_lines: &[&str],
+) {
+    let end = end.min(edits.len());
+    let hunk = &edits[start..end];
+
+    // The position of the hunk is the number of lines which precede it on each side.
+    let (old_position, new_position) =
+        edits[..start]
+            .iter()
+            .fold((0, 0), |(old, new), edit| match edit {
+                Edit::Equal(_, _) => (old + 1, new + 1),
+                Edit::Delete(_) => (old + 1, new),
+                Edit::Insert(_) => (old, new + 1),
+            });
+    let old_count = hunk
+        .iter()
+        .filter(|edit| !matches!(edit, Edit::Insert(_)))
+        .count();
+    let new_count = hunk
+        .iter()
+        .filter(|edit| !matches!(edit, Edit::Delete(_)))
+        .count();
+
+    result.push_str(&format!(
+        "@@ -{} +{} @@\n",
+        hunk_range(old_position, old_count),
+        hunk_range(new_position, new_count)
+    ));
+    for edit in hunk {
+        let (prefix, line) = match *edit {
+            Edit::Equal(old_index, _) => (' ', old_lines[old_index]),
+            Edit::Delete(old_index) => ('-', old_lines[old_index]),
+            Edit::Insert(new_index) => ('+', new_lines[new_index]),
+        };
+        result.push(prefix);
+        result.push_str(line);
+        result.push('\n');
+    }
+}
+
+fn hunk_range(position: usize, count: usize) -> String {
+    match count {
+        // An empty range is identified by the line before it.
+        0 => format!("{},0", position),
+        1 => format!("{}", position + 1),
+        _ => format!("{},{}", position + 1, count),
+    }
+}
+
+/// Finds the shortest edit script from `old` to `new` with the linear space variant of Myers'
+/// algorithm.
+///
+/// A files list is often diffed against itself sorted, which moves most of its lines, so the
+/// edit script may be about as long as the files list.  The linear space variant keeps memory
+/// proportional to the number of lines instead of to the number of lines times the number of
+/// edits.
+fn shortest_edit(old: &[&str], new: &[&str]) -> Vec<Edit> {
+    let mut edits = Vec::with_capacity(old.len() + new.len());
+    push_shortest_edit(old, new, (0, old.len()), (0, new.len()), &mut edits);
+    edits
+}
+
+/// Pushes the shortest edit script from the lines of `old` in `old_range` to the lines of `new`
+/// in `new_range` onto `edits`.
+///
+/// The lines which both ranges start and end with are equal.  The rest is split at the middle
+/// snake of the shortest edit script and each part is diffed in turn.
+fn push_shortest_edit(
+    old: &[&str],
+    new: &[&str],
+    old_range: (usize, usize),
+    new_range: (usize, usize),
+    edits: &mut Vec<Edit>,
+) {
+    let ((mut old_start, mut old_end), (mut new_start, mut new_end)) = (old_range, new_range);
+    while old_start < old_end && new_start < new_end && old[old_start] == new[new_start] {
+        edits.push(Edit::Equal(old_start, new_start));
+        old_start += 1;
+        new_start += 1;
+    }
+    let mut suffix = 0;
+    while old_start < old_end && new_start < new_end && old[old_end - 1] == new[new_end - 1] {
+        old_end -= 1;
+        new_end -= 1;
+        suffix += 1;
+    }
+
+    if old_start == old_end {
+        edits.extend((new_start..new_end).map(Edit::Insert));
+    } else if new_start == new_end {
+        edits.extend((old_start..old_end).map(Edit::Delete));
+    } else {
+        let ((x, y), (u, v)) = middle_snake(&old[old_start..old_end], &new[new_start..new_end]);
+        push_shortest_edit(
+            old,
+            new,
+            (old_start, old_start + x),
+            (new_start, new_start + y),
+            edits,
+        );
+        edits.extend((0..u - x).map(|i| Edit::Equal(old_start + x + i, new_start + y + i)));
+        push_shortest_edit(
+            old,
+            new,
+            (old_start + u, old_end),
+            (new_start + v, new_end),
+            edits,
+        );
+    }
+
+    edits.extend((0..suffix).map(|i| Edit::Equal(old_end + i, new_end + i)));
+}
+
+/// Returns the start and the end of the middle snake of the shortest edit script from `old` to
+/// `new`, which must differ in their first and in their last lines.
+///
+/// The search runs forward from the start and backward from the end at once, one edit at a
+/// time, until the furthest paths on a diagonal overlap.  The snake of the path which reached
+/// the overlap splits the edit script into two halves with at most half of the edits each.
+fn middle_snake(old: &[&str], new: &[&str]) -> ((usize, usize), (usize, usize)) {
+    let old_len = old.len() as isize;
+    let new_len = new.len() as isize;
+    let delta = old_len - new_len;
+    let max = (old_len + new_len + 1) / 2;
+    let offset = max + 1;
+    let index = |k: isize| (k + offset) as usize;
+    // `forward[k]` is the furthest `x` on diagonal `k = x - y` from the start, and `backward[k]`
+    // the furthest distance from the end on diagonal `k` of the reversed lines, which is
+    // diagonal `delta - k` from the start.
+    let mut forward = vec![0isize; 2 * max as usize + 3];
+    let mut backward = vec![0isize; 2 * max as usize + 3];
+    // On diagonal `k` after `d` edits, the path either moves down from diagonal `k + 1` (an
+    // insertion) or right from diagonal `k - 1` (a deletion), whichever reaches further.
+    let moves_down = |furthest: &[isize], k: isize, d: isize| {
+        k == -d || (k != d && furthest[index(k - 1)] < furthest[index(k + 1)])
+    };
+
+    for d in 0..=max {
+        let mut k = -d;
+        while k <= d {
+            let start_x = if moves_down(&forward, k, d) {
+                forward[index(k + 1)]
+            } else {
+                forward[index(k - 1)] + 1
+            };
+            let start_y = start_x - k;
+            let (mut x, mut y) = (start_x, start_y);
+            while x < old_len && y < new_len && old[x as usize] == new[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            forward[index(k)] = x;
+            let reverse_k = delta - k;
+            if delta % 2 != 0
+                && -d < reverse_k
+                && reverse_k < d
+                && x + backward[index(reverse_k)] >= old_len
+            {
+                return (
+                    (start_x as usize, start_y as usize),
+                    (x as usize, y as usize),
+                );
+            }
+            k += 2;
+        }
+
+        let mut k = -d;
+        while k <= d {
+            let start_x = if moves_down(&backward, k, d) {
+                backward[index(k + 1)]
+            } else {
+                backward[index(k - 1)] + 1
+            };
+            let start_y = start_x - k;
+            let (mut x, mut y) = (start_x, start_y);
+            while x < old_len
+                && y < new_len
+                && old[(old_len - x - 1) as usize] == new[(new_len - y - 1) as usize]
+            {
+                x += 1;
+                y += 1;
+            }
+            backward[index(k)] = x;
+            let forward_k = delta - k;
+            if delta % 2 == 0
+                && -d <= forward_k
+                && forward_k <= d
+                && x + forward[index(forward_k)] >= old_len
+            {
+                return (
+                    ((old_len - x) as usize, (new_len - y) as usize),
+                    ((old_len - start_x) as usize, (new_len - start_y) as usize),
+                );
+            }
+            k += 2;
+        }
+    }
+    unreachable!("the forward and backward paths always overlap")
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn unified_diff_of_equal_lines_is_empty() {
+        let lines = ["a", "b", "c"];
+        assert_eq!("", unified_diff("old", "new", &lines, &lines));
+        assert_eq!("", unified_diff::<&str>("old", "new", &[], &[]));
+    }
+
+    #[test]
+    fn unified_diff_groups_changes_into_hunks() {
+        let old_lines: Vec<String> = (1..=20).map(|line| line.to_string()).collect();
+        let mut new_lines = old_lines.clone();
+        new_lines.insert(1, "1a".to_string());
+        new_lines.remove(4);
+        new_lines.push("21".to_string());
+
+        assert_eq!(
+            "--- old\n\
+             +++ new\n\
+             @@ -1,7 +1,7 @@\n \
+             1\n\
+             +1a\n \
+             2\n \
+             3\n\
+             -4\n \
+             5\n \
+             6\n \
+             7\n\
+             @@ -18,3 +18,4 @@\n \
+             18\n \
+             19\n \
+             20\n\
+             +21\n",
+            unified_diff("old", "new", &old_lines, &new_lines)
+        );
+    }
+
+    #[test]
+    fn shortest_edit_is_shortest() {
+        // Sequences of lines from a small alphabet, from a linear congruential generator.
+        let mut state: u32 = 1;
+        let mut lines = |count: usize| -> Vec<&str> {
+            (0..count)
+                .map(|_| {
+                    state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                    ["a", "b", "c", "d"][(state >> 16) as usize % 4]
+                })
+                .collect()
+        };
+        for _ in 0..500 {
+            let old = lines(12);
+            let new = lines(9);
+            let edits = shortest_edit(&old, &new);
+
+            let old_side: Vec<&str> = edits
+                .iter()
+                .filter_map(|edit| match *edit {
+                    Edit::Equal(old_index, _) | Edit::Delete(old_index) => Some(old[old_index]),
+                    Edit::Insert(_) => None,
+                })
+                .collect();
+            let new_side: Vec<&str> = edits
+                .iter()
+                .filter_map(|edit| match *edit {
+                    Edit::Equal(_, new_index) | Edit::Insert(new_index) => Some(new[new_index]),
+                    Edit::Delete(_) => None,
+                })
+                .collect();
+            assert_eq!(old, old_side);
+            assert_eq!(new, new_side);
+            for edit in edits.iter() {
+                if let Edit::Equal(old_index, new_index) = *edit {
+                    assert_eq!(old[old_index], new[new_index]);
+                }
+            }
+
+            // The length of the longest common subsequence.
+            let mut common = vec![vec![0; new.len() + 1]; old.len() + 1];
+            for x in 0..old.len() {
+                for y in 0..new.len() {
+                    common[x + 1][y + 1] = if old[x] == new[y] {
+                        common[x][y] + 1
+                    } else {
+                        common[x][y + 1].max(common[x + 1][y])
+                    };
+                }
+            }
+            let equal = edits
+                .iter()
+                .filter(|edit| matches!(edit, Edit::Equal(_, _)))
+                .count();
+            assert_eq!(common[old.len()][new.len()], equal);
+        }
+    }
+
+    #[test]
+    fn unified_diff_of_reversed_lines() {
+        let old_lines: Vec<String> = (0..2_000).map(|line| line.to_string()).collect();
+        let new_lines: Vec<String> = old_lines.iter().rev().cloned().collect();
+        let diff = unified_diff("old", "new", &old_lines, &new_lines);
+        assert_eq!(
+            (old_lines.len() - 1, old_lines.len() - 1),
+            (
+                diff.lines().filter(|line| line.starts_with('-')).count() - 1,
+                diff.lines().filter(|line| line.starts_with('+')).count() - 1
+            )
+        );
+    }
+
+    #[test]
+    fn unified_diff_of_empty_file() {
+        assert_eq!(
+            "--- old\n+++ new\n@@ -0,0 +1,2 @@\n+a\n+b\n",
+            unified_diff("old", "new", &[], &["a", "b"])
+        );
+        assert_eq!(
+            "--- old\n+++ new\n@@ -1 +0,0 @@\n-a\n",
+            unified_diff("old", "new", &["a"], &[])
+        );
+    }
+}
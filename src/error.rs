@@ -1,18 +1,35 @@
 //! Error type for wheelhoss
+use std::collections::{BTreeMap, BTreeSet};
 use std::fmt::{Display, Formatter};
 
 #[derive(Debug)]
 pub enum Error {
+    DanglingSymlinks(BTreeMap<String, String>),
     FailedToOpenFile(String, Option<std::io::Error>),
-    FailedToSplitFilename(String),
+    FilesListNotLoaded(String),
     FilesListWriteIncomplete(String),
+    InvalidKeyring(String, String),
+    InvalidOptionValue(String, String),
+    InvalidPkgdef(String),
     IoError(std::io::Error),
+    MissingPackageFiles(BTreeSet<String>),
+    NoCacheTag(String),
+    NoManifest(String),
+    NoSigningKey(String),
+    PythonInterpreterFailed(String, String),
     UnableToProcessNonUtf8Path(String),
 }
 
 impl Display for Error {
     fn fmt(&self, f: &mut Formatter) -> Result<(), std::fmt::Error> {
         match self {
+            Self::DanglingSymlinks(links) => {
+                write!(f, "Dangling symbolic links:")?;
+                for (link, target) in links.iter() {
+                    write!(f, "\n\t{} -> {}", link, target)?;
+                }
+                Ok(())
+            }
             Self::FailedToOpenFile(filename, maybe_error) => {
                 let error = match maybe_error {
                     Some(error) => format!(": {}", error),
@@ -20,13 +37,49 @@ impl Display for Error {
                 };
                 write!(f, "Failed to open file \"{}\"{}", filename, error)
             }
-            Self::FailedToSplitFilename(filename) => {
-                write!(f, "Failed to split filename on \".cpython-\": {}", filename)
+            Self::FilesListNotLoaded(filename) => {
+                write!(f, "Files list was saved without being loaded: {}", filename)
             }
             Self::FilesListWriteIncomplete(filename) => {
                 write!(f, "Write to files list was incomplete: {}", filename)
             }
+            Self::InvalidKeyring(filename, message) => {
+                write!(f, "Invalid keyring \"{}\": {}", filename, message)
+            }
+            Self::InvalidOptionValue(option, value) => {
+                write!(f, "Invalid value for {}: {}", option, value)
+            }
+            Self::InvalidPkgdef(message) => write!(f, "Invalid package definition: {}", message),
             Self::IoError(err) => write!(f, "IoError: {}", err),
+            Self::MissingPackageFiles(paths) => {
+                write!(f, "Files which the source map does not find:")?;
+                for path in paths.iter() {
+                    write!(f, "\n\t{}", path)?;
+                }
+                Ok(())
+            }
+            Self::NoCacheTag(filename) => write!(
+                f,
+                "Failed to find a Python cache tag in the bytecode files of the files list: {}",
+                filename
+            ),
+            Self::NoManifest(path) => {
+                write!(
+                    f,
+                    "No {} in the package, so Sandstorm would not install it",
+                    path
+                )
+            }
+            Self::NoSigningKey(app_id) => {
+                write!(f, "No signing key for app ID {} in the keyring", app_id)
+            }
+            Self::PythonInterpreterFailed(interpreter, message) => {
+                write!(
+                    f,
+                    "Python interpreter \"{}\" failed: {}",
+                    interpreter, message
+                )
+            }
             Self::UnableToProcessNonUtf8Path(path) => {
                 write!(f, "Unable to process this non-UTF-8 path: {}", path)
             }
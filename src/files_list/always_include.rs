@@ -0,0 +1,276 @@
+//! Entries of a `FilesList` which belong in `alwaysInclude` of the package definition
+
+use std::collections::BTreeSet;
+use std::path::Path;
+
+use super::{EntryKind, FilesList};
+use crate::error::Error;
+use crate::pkgdef::Pkgdef;
+
+impl FilesList {
+    /// Moves the entries beneath `prefixes` from the files list file to `alwaysInclude` of the
+    /// package definition file `pkgdef_path`.
+    ///
+    /// `move_entries_to_always_include` reads both files, moves entries as described by
+    /// `move_to_always_include` and writes both files.  The package definition file is written
+    /// first, so that the entries are added to `alwaysInclude` before they are removed from the
+    /// files list file, and are in one of the files even if writing the files list file fails.
+    ///
+    /// The files list file stays locked throughout, but the package definition file is not
+    /// locked, as `spk` and editors do not lock it either.  Changes which another process makes to
+    /// the package definition file meanwhile are overwritten.
+    pub fn move_entries_to_always_include<S: AsRef<str>>(
+        &mut self,
+        pkgdef_path: &Path,
+        prefixes: &[S],
+    ) -> Result<BTreeSet<String>, Error> {
+        self.load()?;
+        let mut pkgdef = Pkgdef::read(pkgdef_path)?;
+        let removed_entries = self.move_to_always_include(&mut pkgdef, prefixes);
+        pkgdef.write(pkgdef_path)?;
+        self.save()?;
+        Ok(removed_entries)
+    }
+
+    /// Moves the entries beneath `prefixes` from the files list to `alwaysInclude` of `pkgdef`
+    /// without writing either file.
+    ///
+    /// A directory such as `opt/app` is better included whole through `alwaysInclude` than
+    /// listed file by file.  Each prefix is added to `alwaysInclude` and the entries which are
+    /// the prefix or beneath it are removed from the files list.  The removed entries are
+    /// returned with a leading `/`.
+    pub fn move_to_always_include<S: AsRef<str>>(
+        &mut self,
+        pkgdef: &mut Pkgdef,
+        prefixes: &[S],
+    ) -> BTreeSet<String> {
+        let mut removed_entries: BTreeSet<String> = BTreeSet::new();
+        for prefix in prefixes {
+            let prefix = FilesList::normalize_entry(prefix.as_ref()).trim_end_matches('/');
+            if prefix.is_empty() {
+                continue;
+            }
+            let beneath: Vec<String> = self
+                .entries()
+                .filter(|entry| is_beneath(prefix, entry))
+                .map(String::from)
+                .collect();
+            for entry in beneath {
+                self.remove(&entry);
+                removed_entries.insert(format!("/{}", entry));
+            }
+            if !pkgdef
+                .always_include
+                .iter()
+                .any(|path| FilesList::normalize_entry(path).trim_end_matches('/') == prefix)
+            {
+                pkgdef.always_include.push(prefix.to_string());
+            }
+        }
+        removed_entries
+    }
+
+    /// Moves the paths in `alwaysInclude` of the package definition file `pkgdef_path` which are
+    /// beneath `prefixes` to the files list file.
+    ///
+    /// `move_entries_from_always_include` reads both files, moves entries as described by
+    /// `move_from_always_include` and writes both files.  The files list file is written first, so
+    /// that the entries are added to the files list file before they are removed from
+    /// `alwaysInclude`, and are in one of the files even if writing the package definition file
+    /// fails.
+    ///
+    /// As with `move_entries_to_always_include`, only the files list file is locked.
+    pub fn move_entries_from_always_include<S: AsRef<str>>(
+        &mut self,
+        pkgdef_path: &Path,
+        prefixes: &[S],
+    ) -> Result<BTreeSet<String>, Error> {
+        self.load()?;
+        let mut pkgdef = Pkgdef::read(pkgdef_path)?;
+        let added_entries = self.move_from_always_include(&mut pkgdef, prefixes)?;
+        self.save()?;
+        pkgdef.write(pkgdef_path)?;
+        Ok(added_entries)
+    }
+
+    /// Moves the paths in `alwaysInclude` of `pkgdef` which are `prefixes` or beneath them to the
+    /// files list without writing either file.
+    ///
+    /// Each such path is removed from `alwaysInclude` and, if it is a directory beneath the root,
+    /// the files and symbolic links beneath it are added to the files list, so that they can be
+    /// pruned one by one.  Otherwise, or if the directory is empty, the path itself is added.  The
+    /// added entries are returned with a leading `/`.
+    ///
+    /// If the source map set by `set_source_map` finds neither the path nor any of its entries,
+    /// the path stays in `alwaysInclude` and nothing is added.  Otherwise the entries which it
+    /// does not find, such as those in `hidePaths`, are left out, as `spk pack` leaves them out of
+    /// the directories in `alwaysInclude`.  Either way, the paths and entries which were not found
+    /// are reported by `unreachable_entries`.
+    pub fn move_from_always_include<S: AsRef<str>>(
+        &mut self,
+        pkgdef: &mut Pkgdef,
+        prefixes: &[S],
+    ) -> Result<BTreeSet<String>, Error> {
+        let prefixes: Vec<&str> = prefixes
+            .iter()
+            .map(|prefix| FilesList::normalize_entry(prefix.as_ref()).trim_end_matches('/'))
+            .filter(|prefix| !prefix.is_empty())
+            .collect();
+        let mut added_entries: BTreeSet<String> = BTreeSet::new();
+        let mut kept: Vec<String> = Vec::new();
+
+        for path in pkgdef.always_include.iter() {
+            let entry = FilesList::normalize_entry(path).trim_end_matches('/');
+            if !prefixes.iter().any(|prefix| is_beneath(prefix, entry)) {
+                kept.push(path.clone());
+                continue;
+            }
+            if !self.is_reachable(entry)? {
+                self.unreachable.insert(format!("/{}", entry));
+                kept.push(path.clone());
+                continue;
+            }
+            let mut entries = if self.entry_kind(entry)? == EntryKind::Directory {
+                self.files_beneath(entry)?
+            } else {
+                BTreeSet::new()
+            };
+            if entries.is_empty() {
+                entries.insert(entry.to_string());
+            }
+            let mut is_reachable = false;
+            for entry in entries.iter() {
+                is_reachable |= self.contains(entry) || self.is_reachable(entry)?;
+            }
+            if !is_reachable {
+                self.unreachable
+                    .extend(entries.iter().map(|entry| format!("/{}", entry)));
+                kept.push(path.clone());
+                continue;
+            }
+            self.insert_reachable_entries(&mut entries)?;
+            added_entries.extend(entries.iter().map(|entry| format!("/{}", entry)));
+        }
+        pkgdef.always_include = kept;
+
+        Ok(added_entries)
+    }
+}
+
+/// Returns `true` if `entry` is `prefix` or beneath it.
+fn is_beneath(prefix: &str, entry: &str) -> bool {
+    match entry.strip_prefix(prefix) {
+        Some(rest) => rest.is_empty() || rest.starts_with('/'),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::files_list::tests::test_tree::tree;
+
+    #[test]
+    fn files_list_move_to_and_from_always_include() {
+        let (root, files_list_file) = tree(
+            &[
+                "opt/app/manage.py",
+                "opt/app/static/site.css",
+                "opt/application.py",
+            ],
+            "opt/app/manage.py\n\
+             # Static files\n\
+             opt/app/static/site.css\n\
+             opt/application.py\n",
+        );
+        let pkgdef_file = root.path().join("sandstorm-pkgdef.capnp");
+        let pkgdef = "const pkgdef :Spk.PackageDefinition = (\n  \
+                      # Files which are always included.\n  \
+                      alwaysInclude = [ \"usr/share/zoneinfo\" ],\n);\n";
+        std::fs::write(&pkgdef_file, pkgdef).unwrap();
+
+        let mut files_list = FilesList::with_root(&files_list_file, root.path());
+        let removed = files_list
+            .move_entries_to_always_include(&pkgdef_file, &["/opt/app/"])
+            .unwrap();
+        assert_eq!(
+            vec!["/opt/app/manage.py", "/opt/app/static/site.css"],
+            removed.into_iter().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec!["# Static files", "opt/application.py"],
+            files_list.lines().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            pkgdef.replace(
+                "[ \"usr/share/zoneinfo\" ]",
+                "[ \"usr/share/zoneinfo\", \"opt/app\" ]"
+            ),
+            std::fs::read_to_string(&pkgdef_file).unwrap()
+        );
+
+        let added = files_list
+            .move_entries_from_always_include(&pkgdef_file, &["opt"])
+            .unwrap();
+        assert_eq!(
+            vec!["/opt/app/manage.py", "/opt/app/static/site.css"],
+            added.into_iter().collect::<Vec<_>>()
+        );
+        assert_eq!(pkgdef, std::fs::read_to_string(&pkgdef_file).unwrap());
+        drop(files_list);
+        assert_eq!(
+            "# Static files\n\
+             opt/app/manage.py\n\
+             opt/app/static/site.css\n\
+             opt/application.py\n",
+            std::fs::read_to_string(&files_list_file).unwrap()
+        );
+    }
+
+    #[test]
+    fn files_list_move_from_always_include_skips_unreachable_entries() {
+        let (root, files_list_file) = tree(
+            &[
+                "opt/app/manage.py",
+                "opt/app/secret.key",
+                "opt/cache/index",
+                "opt/hidden/notes.txt",
+            ],
+            "",
+        );
+        std::fs::create_dir(root.path().join("opt/data")).unwrap();
+        let mut pkgdef = Pkgdef::parse(
+            "const pkgdef :Spk.PackageDefinition = (\n\
+               sourceMap = (searchPath = [\n\
+                 (sourcePath = \"/\", hidePaths = [\"opt/app/secret.key\", \
+                                                   \"opt/cache/index\", \"opt/hidden\"])\n\
+               ]),\n\
+               alwaysInclude = [\"opt/app\", \"opt/cache\", \"opt/data\", \"opt/hidden\"]\n\
+             );\n",
+        )
+        .unwrap();
+
+        let mut files_list = FilesList::with_root(&files_list_file, root.path());
+        files_list.set_source_map(pkgdef.source_map.clone(), root.path());
+        files_list.load().unwrap();
+        let added = files_list
+            .move_from_always_include(&mut pkgdef, &["opt"])
+            .unwrap();
+        assert_eq!(
+            vec!["/opt/app/manage.py", "/opt/data"],
+            added.into_iter().collect::<Vec<_>>()
+        );
+        assert_eq!(vec!["opt/cache", "opt/hidden"], pkgdef.always_include);
+        assert!(!files_list.contains("opt/app/secret.key"));
+        assert_eq!(
+            vec!["/opt/app/secret.key", "/opt/cache/index", "/opt/hidden"],
+            files_list
+                .unreachable_entries()
+                .iter()
+                .map(String::as_str)
+                .collect::<Vec<_>>()
+        );
+    }
+}
@@ -0,0 +1,203 @@
+//! Python bytecode file names
+//!
+//! `bytecode` parses the names of Python bytecode files in `__pycache__` directories, which are
+//! described by [PEP 3147](https://peps.python.org/pep-3147/) and
+//! [PEP 488](https://peps.python.org/pep-0488/), and the names of legacy bytecode files which sit
+//! beside their source files.
+
+const OPTIMIZATION_PREFIX: &str = "opt-";
+pub(crate) const PYC_EXTENSION: &str = ".pyc";
+pub(crate) const PYCACHE_DIRECTORY: &str = "__pycache__";
+const PYO_EXTENSION: &str = ".pyo";
+pub(crate) const PY_EXTENSION: &str = ".py";
+
+/// Returns the name of the bytecode file for `module` in a `__pycache__` directory.
+pub(crate) fn cached_bytecode_name(
+    module: &str,
+    cache_tag: &str,
+    optimization: Option<&str>,
+) -> String {
+    match optimization {
+        Some(optimization) => format!(
+            "{}.{}.{}{}{}",
+            module, cache_tag, OPTIMIZATION_PREFIX, optimization, PYC_EXTENSION
+        ),
+        None => format!("{}.{}{}", module, cache_tag, PYC_EXTENSION),
+    }
+}
+
+/// Returns the path of the source file for the legacy bytecode file at `path`.
+///
+/// Python 2 and `python3 -m compileall -b` write `module.pyc`, or `module.pyo` for Python 2 with
+/// `-O`, beside `module.py`.  Returns `None` if `path` is not a legacy bytecode file.
+pub(crate) fn legacy_bytecode_source(path: &str) -> Option<String> {
+    if path.contains(PYCACHE_DIRECTORY) {
+        return None;
+    }
+    let without_extension = path
+        .strip_suffix(PYC_EXTENSION)
+        .or_else(|| path.strip_suffix(PYO_EXTENSION))?;
+    match without_extension.rsplit('/').next() {
+        Some(module) if !module.is_empty() => {
+            Some(format!("{}{}", without_extension, PY_EXTENSION))
+        }
+        _ => None,
+    }
+}
+
+/// The parts of the name of a bytecode file in a `__pycache__` directory.
+///
+/// `__init__.cpython-39.pyc` is the bytecode for module `__init__` compiled by an interpreter with
+/// cache tag `cpython-39`.  `__init__.cpython-39.opt-1.pyc` is the same, compiled with `-O`, and
+/// `__init__.cpython-39.opt-2.pyc` is compiled with `-OO`.  Module names cannot contain `.`, so the
+/// cache tag is everything between the first `.` and the optimization level or the extension.
+#[derive(Debug, PartialEq)]
+pub(crate) struct CachedBytecode<'a> {
+    pub module: &'a str,
+    pub cache_tag: &'a str,
+    pub optimization: Option<&'a str>,
+}
+
+impl<'a> CachedBytecode<'a> {
+    /// Parses `file_name`, returning `None` if it is not the name of a cached bytecode file.
+    pub fn parse(file_name: &'a str) -> Option<Self> {
+        let without_extension = file_name.strip_suffix(PYC_EXTENSION)?;
+        let (module, cache_tag) = without_extension.split_once('.')?;
+        let (cache_tag, optimization) = match cache_tag.split_once('.') {
+            Some((cache_tag, optimization)) => {
+                let optimization = optimization.strip_prefix(OPTIMIZATION_PREFIX)?;
+                if optimization.is_empty()
+                    || !optimization.chars().all(|c| c.is_ascii_alphanumeric())
+                {
+                    return None;
+                }
+                (cache_tag, Some(optimization))
+            }
+            None => (cache_tag, None),
+        };
+        if module.is_empty() || cache_tag.is_empty() {
+            return None;
+        }
+        Some(Self {
+            module,
+            cache_tag,
+            optimization,
+        })
+    }
+
+    /// Returns `true` if the cache tag belongs to one of `implementation_tags`.
+    ///
+    /// A cache tag belongs to an implementation when it starts with the implementation's tag, such
+    /// as `cpython` for `cpython-39` or `pypy` for `pypy39`.
+    pub fn is_from<S: AsRef<str>>(&self, implementation_tags: &[S]) -> bool {
+        implementation_tags
+            .iter()
+            .any(|tag| self.cache_tag.starts_with(tag.as_ref()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn cached_bytecode_parse() {
+        assert_eq!(
+            Some(CachedBytecode {
+                module: "__init__",
+                cache_tag: "cpython-39",
+                optimization: None
+            }),
+            CachedBytecode::parse("__init__.cpython-39.pyc")
+        );
+        assert_eq!(
+            Some(CachedBytecode {
+                module: "shortcuts",
+                cache_tag: "pypy39",
+                optimization: None
+            }),
+            CachedBytecode::parse("shortcuts.pypy39.pyc")
+        );
+        assert_eq!(
+            Some(CachedBytecode {
+                module: "views",
+                cache_tag: "graalpy231-310",
+                optimization: None
+            }),
+            CachedBytecode::parse("views.graalpy231-310.pyc")
+        );
+        assert_eq!(None, CachedBytecode::parse("views.pyc"));
+        assert_eq!(None, CachedBytecode::parse("views.cpython-39.opt.pyc"));
+        assert_eq!(None, CachedBytecode::parse("views.cpython-39.opt-.pyc"));
+        assert_eq!(None, CachedBytecode::parse("views.cpython-39.debug.pyc"));
+        assert_eq!(None, CachedBytecode::parse("views.cpython-39.py"));
+        assert_eq!(None, CachedBytecode::parse(".cpython-39.pyc"));
+    }
+
+    #[test]
+    fn cached_bytecode_parse_optimized() {
+        assert_eq!(
+            Some(CachedBytecode {
+                module: "__init__",
+                cache_tag: "cpython-310",
+                optimization: Some("1")
+            }),
+            CachedBytecode::parse("__init__.cpython-310.opt-1.pyc")
+        );
+        assert_eq!(
+            Some(CachedBytecode {
+                module: "foo",
+                cache_tag: "pypy39",
+                optimization: Some("2")
+            }),
+            CachedBytecode::parse("foo.pypy39.opt-2.pyc")
+        );
+    }
+
+    #[test]
+    fn cached_bytecode_name_round_trip() {
+        for (module, cache_tag, optimization) in [
+            ("views", "cpython-39", None),
+            ("__init__", "cpython-310", Some("2")),
+            ("urls", "pypy39", Some("1")),
+        ] {
+            let name = cached_bytecode_name(module, cache_tag, optimization);
+            assert_eq!(
+                Some(CachedBytecode {
+                    module,
+                    cache_tag,
+                    optimization
+                }),
+                CachedBytecode::parse(&name)
+            );
+        }
+    }
+
+    #[test]
+    fn legacy_bytecode_source_for_paths() {
+        assert_eq!(
+            Some("opt/app/module.py".to_string()),
+            legacy_bytecode_source("opt/app/module.pyc")
+        );
+        assert_eq!(
+            Some("opt/app/module.py".to_string()),
+            legacy_bytecode_source("opt/app/module.pyo")
+        );
+        assert_eq!(None, legacy_bytecode_source("opt/app/module.py"));
+        assert_eq!(None, legacy_bytecode_source("opt/app/.pyc"));
+        assert_eq!(
+            None,
+            legacy_bytecode_source("opt/app/__pycache__/module.cpython-39.pyc")
+        );
+    }
+
+    #[test]
+    fn cached_bytecode_is_from() {
+        let bytecode = CachedBytecode::parse("views.pypy39.pyc").unwrap();
+        assert!(bytecode.is_from(&["cpython", "pypy"]));
+        assert!(!bytecode.is_from(&["cpython", "graalpy"]));
+        assert!(!bytecode.is_from::<&str>(&[]));
+    }
+}
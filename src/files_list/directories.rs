@@ -0,0 +1,222 @@
+//! Directories in a `FilesList`
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::ErrorKind;
+
+use super::{join, parent_of, FilesList};
+use crate::error::Error;
+
+/// The number of files which are not listed beneath a listed directory above which the directory
+/// is considered to bloat the package.
+pub const LARGE_UNTRACKED_FILES: usize = 100;
+
+/// What an entry is beneath the root.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EntryKind {
+    File,
+    Directory,
+    Symlink,
+    /// A device, socket or pipe.
+    Other,
+    Missing,
+}
+
+/// A listed directory, which adds everything beneath it to the package.
+#[derive(Debug, PartialEq)]
+pub struct ListedDirectory {
+    /// The directory, with a leading `/`.
+    pub entry: String,
+    /// The number of files and symbolic links beneath the directory.
+    pub files: usize,
+    /// The number of files and symbolic links beneath the directory which are not listed.
+    pub untracked: usize,
+}
+
+impl ListedDirectory {
+    /// Returns `true` if the directory adds more than `LARGE_UNTRACKED_FILES` files which are not
+    /// listed.
+    pub fn is_large(&self) -> bool {
+        self.untracked > LARGE_UNTRACKED_FILES
+    }
+}
+
+impl FilesList {
+    /// Returns what `entry` is beneath the root.
+    ///
+    /// The symbolic links in the directories of `entry` are resolved beneath the root as
+    /// described by `resolve_symlinks`, but `entry` itself is not followed if it is a symbolic
+    /// link.
+    pub fn entry_kind(&self, entry: &str) -> Result<EntryKind, Error> {
+        let entry = FilesList::normalize_entry(entry).trim_end_matches('/');
+        let directory = self.resolve_symlinks(parent_of(entry))?;
+        if !directory.exists {
+            return Ok(EntryKind::Missing);
+        }
+        let name = entry.rsplit('/').next().unwrap_or(entry);
+        match self
+            .resolve(&join(&directory.target, name))
+            .symlink_metadata()
+        {
+            Ok(metadata) if metadata.file_type().is_symlink() => Ok(EntryKind::Symlink),
+            Ok(metadata) if metadata.is_dir() => Ok(EntryKind::Directory),
+            Ok(metadata) if metadata.is_file() => Ok(EntryKind::File),
+            Ok(_) => Ok(EntryKind::Other),
+            Err(err) if matches!(err.kind(), ErrorKind::NotFound | ErrorKind::NotADirectory) => {
+                Ok(EntryKind::Missing)
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Returns what each entry in the files list is beneath the root, by entry.
+    pub fn entry_kinds(&self) -> Result<BTreeMap<String, EntryKind>, Error> {
+        self.entries()
+            .map(|entry| Ok((entry.to_string(), self.entry_kind(entry)?)))
+            .collect()
+    }
+
+    /// Returns the listed directories with the number of files beneath them which are not
+    /// listed.
+    pub fn listed_directories(&self) -> Result<Vec<ListedDirectory>, Error> {
+        let mut directories = Vec::new();
+        for entry in self.entries() {
+            if self.entry_kind(entry)? != EntryKind::Directory {
+                continue;
+            }
+            let files = self.files_beneath(entry)?;
+            directories.push(ListedDirectory {
+                entry: format!("/{}", entry),
+                files: files.len(),
+                untracked: files.iter().filter(|file| !self.contains(file)).count(),
+            });
+        }
+        Ok(directories)
+    }
+
+    /// Replaces listed directories with the files beneath them in the files list file.
+    pub fn include_expanded_directories(
+        &mut self,
+        min_untracked: usize,
+    ) -> Result<BTreeSet<String>, Error> {
+        self.load()?;
+        let added_files = self.expand_directories(min_untracked)?;
+        self.save()?;
+        Ok(added_files)
+    }
+
+    /// Replaces listed directories with the files beneath them without writing the files list
+    /// file.
+    ///
+    /// In a Sandstorm package, a listed directory includes everything beneath it, which can bloat
+    /// the package without notice.  Each listed directory with at least `min_untracked` files
+    /// beneath it which are not listed is removed from the files list and the files and symbolic
+    /// links beneath it are added, so that unwanted files can be removed one by one.  Empty
+    /// directories are kept, but empty directories beneath a listed directory are not listed.
+    /// The added files are returned with a leading `/`.
+    pub fn expand_directories(&mut self, min_untracked: usize) -> Result<BTreeSet<String>, Error> {
+        let mut added_files: BTreeSet<String> = BTreeSet::new();
+
+        for directory in self.listed_directories()? {
+            if directory.files == 0 || directory.untracked < min_untracked {
+                continue;
+            }
+            for file in self.files_beneath(&directory.entry)? {
+                if self.insert_reachable(&file)? {
+                    added_files.insert(format!("/{}", file));
+                }
+            }
+            // Removed after the files are inserted, so that its comments move to the first file.
+            self.remove(&directory.entry);
+        }
+
+        Ok(added_files)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::files_list::tests::test_tree::tree;
+
+    #[test]
+    fn files_list_expand_directories() {
+        use std::os::unix::fs::symlink;
+
+        let (root, files_list_file) = tree(
+            &[
+                "opt/app/django-app/app/views.py",
+                "opt/app/django-app/manage.py",
+                "opt/app/django-app/static/site.css",
+                "usr/bin/python3.9",
+            ],
+            "# The application\n\
+             opt/app/django-app\n\
+             opt/app/django-app/manage.py\n\
+             opt/app/empty\n\
+             usr/bin/missing\n\
+             usr/bin/python3\n\
+             usr/bin/python3.9\n",
+        );
+        std::fs::create_dir_all(root.path().join("opt/app/empty")).unwrap();
+        symlink("python3.9", root.path().join("usr/bin/python3")).unwrap();
+
+        let mut files_list = FilesList::with_root(&files_list_file, root.path());
+        files_list.load().unwrap();
+        assert_eq!(
+            BTreeMap::from([
+                ("opt/app/django-app".to_string(), EntryKind::Directory),
+                ("opt/app/django-app/manage.py".to_string(), EntryKind::File),
+                ("opt/app/empty".to_string(), EntryKind::Directory),
+                ("usr/bin/missing".to_string(), EntryKind::Missing),
+                ("usr/bin/python3".to_string(), EntryKind::Symlink),
+                ("usr/bin/python3.9".to_string(), EntryKind::File),
+            ]),
+            files_list.entry_kinds().unwrap()
+        );
+        assert_eq!(
+            vec![
+                ListedDirectory {
+                    entry: "/opt/app/django-app".to_string(),
+                    files: 3,
+                    untracked: 2,
+                },
+                ListedDirectory {
+                    entry: "/opt/app/empty".to_string(),
+                    files: 0,
+                    untracked: 0,
+                },
+            ],
+            files_list.listed_directories().unwrap()
+        );
+        assert!(files_list
+            .listed_directories()
+            .unwrap()
+            .iter()
+            .all(|directory| !directory.is_large()));
+
+        assert!(files_list.expand_directories(3).unwrap().is_empty());
+        let added = files_list.expand_directories(1).unwrap();
+        assert_eq!(
+            vec![
+                "/opt/app/django-app/app/views.py",
+                "/opt/app/django-app/static/site.css",
+            ],
+            added.into_iter().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![
+                "# The application",
+                "opt/app/django-app/app/views.py",
+                "opt/app/django-app/manage.py",
+                "opt/app/django-app/static/site.css",
+                "opt/app/empty",
+                "usr/bin/missing",
+                "usr/bin/python3",
+                "usr/bin/python3.9",
+            ],
+            files_list.lines().collect::<Vec<_>>()
+        );
+    }
+}
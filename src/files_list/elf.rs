@@ -0,0 +1,345 @@
+//! ELF files and the dynamic linker's cache
+//!
+//! `elf` reads the dynamic section and interpreter of executables and shared objects, which are
+//! described by the [System V ABI](https://refspecs.linuxfoundation.org/elf/gabi4+/contents.html),
+//! and the libraries in `/etc/ld.so.cache`, which is written by `ldconfig`.
+
+use std::convert::{TryFrom, TryInto};
+
+const ELF_MAGIC: &[u8] = b"\x7fELF";
+const ELFCLASS64: u8 = 2;
+const ELFDATA2MSB: u8 = 2;
+
+const PT_LOAD: u32 = 1;
+const PT_DYNAMIC: u32 = 2;
+const PT_INTERP: u32 = 3;
+
+const DT_NULL: u64 = 0;
+const DT_NEEDED: u64 = 1;
+const DT_STRTAB: u64 = 5;
+const DT_RPATH: u64 = 15;
+const DT_RUNPATH: u64 = 29;
+
+const LD_SO_CACHE_OLD_MAGIC: &[u8] = b"ld.so-1.7.0";
+const LD_SO_CACHE_NEW_MAGIC: &[u8] = b"glibc-ld.so.cache1.1";
+
+/// The class and machine of an ELF file, which must match for one to load the other.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct ElfIdentity {
+    pub is_64_bit: bool,
+    pub machine: u16,
+}
+
+/// The dynamic linking information of an executable or shared object.
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct ElfFile {
+    /// The program interpreter, such as `/lib64/ld-linux-x86-64.so.2`.
+    pub interpreter: Option<String>,
+    /// The `DT_NEEDED` libraries, such as `libc.so.6`.
+    pub needed: Vec<String>,
+    /// The directories of `DT_RPATH`.
+    pub rpath: Vec<String>,
+    /// The directories of `DT_RUNPATH`.
+    pub runpath: Vec<String>,
+}
+
+/// Reads integers from an ELF file of either class and byte order.
+struct Reader<'a> {
+    data: &'a [u8],
+    is_64_bit: bool,
+    is_big_endian: bool,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes<const N: usize>(&self, offset: u64) -> Option<[u8; N]> {
+        let start = usize::try_from(offset).ok()?;
+        let end = start.checked_add(N)?;
+        let mut bytes: [u8; N] = self.data.get(start..end)?.try_into().ok()?;
+        if self.is_big_endian {
+            bytes.reverse();
+        }
+        Some(bytes)
+    }
+
+    fn u16(&self, offset: u64) -> Option<u16> {
+        self.bytes(offset).map(u16::from_le_bytes)
+    }
+
+    fn u32(&self, offset: u64) -> Option<u32> {
+        self.bytes(offset).map(u32::from_le_bytes)
+    }
+
+    /// Reads an address, offset or size, which is 8 bytes in a 64-bit file and 4 bytes otherwise.
+    fn word(&self, offset: u64) -> Option<u64> {
+        if self.is_64_bit {
+            self.bytes(offset).map(u64::from_le_bytes)
+        } else {
+            self.u32(offset).map(u64::from)
+        }
+    }
+
+    /// Reads a string which ends with a NUL byte.
+    fn string(&self, offset: u64) -> Option<String> {
+        let start = usize::try_from(offset).ok()?;
+        let length = self.data.get(start..)?.iter().position(|&byte| byte == 0)?;
+        String::from_utf8(self.data[start..start + length].to_vec()).ok()
+    }
+}
+
+impl ElfIdentity {
+    /// Reads the identity from the start of a file, returning `None` if it is not an ELF file.
+    pub fn parse(header: &[u8]) -> Option<Self> {
+        let reader = ElfFile::reader(header)?;
+        Some(Self {
+            is_64_bit: reader.is_64_bit,
+            machine: reader.u16(18)?,
+        })
+    }
+}
+
+impl ElfFile {
+    /// Parses the contents of an ELF file, returning `None` if it is not an ELF file or is
+    /// malformed.
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        let reader = ElfFile::reader(data)?;
+        let (program_headers, entry_size, count) = if reader.is_64_bit {
+            (reader.word(32)?, reader.u16(54)?, reader.u16(56)?)
+        } else {
+            (reader.word(28)?, reader.u16(42)?, reader.u16(44)?)
+        };
+        // The offset, virtual address and size in the file of each segment.
+        let segment = |index: u16| -> Option<(u32, u64, u64, u64)> {
+            let header = program_headers.checked_add(u64::from(index) * u64::from(entry_size))?;
+            let kind = reader.u32(header)?;
+            if reader.is_64_bit {
+                Some((
+                    kind,
+                    reader.word(header + 8)?,
+                    reader.word(header + 16)?,
+                    reader.word(header + 32)?,
+                ))
+            } else {
+                Some((
+                    kind,
+                    reader.word(header + 4)?,
+                    reader.word(header + 8)?,
+                    reader.word(header + 16)?,
+                ))
+            }
+        };
+
+        let mut elf_file = ElfFile::default();
+        let mut loads = Vec::new();
+        let mut dynamic = None;
+        for index in 0..count {
+            let (kind, offset, address, size) = segment(index)?;
+            match kind {
+                PT_LOAD => loads.push((offset, address, size)),
+                PT_DYNAMIC => dynamic = Some((offset, size)),
+                PT_INTERP => elf_file.interpreter = reader.string(offset),
+                _ => {}
+            }
+        }
+        let (dynamic_offset, dynamic_size) = match dynamic {
+            Some(dynamic) => dynamic,
+            None => return Some(elf_file),
+        };
+
+        let entry_size = if reader.is_64_bit { 16 } else { 8 };
+        let mut entries = Vec::new();
+        let mut string_table = None;
+        for offset in
+            (dynamic_offset..dynamic_offset.saturating_add(dynamic_size)).step_by(entry_size)
+        {
+            let tag = reader.word(offset)?;
+            let value = reader.word(offset.checked_add(entry_size as u64 / 2)?)?;
+            match tag {
+                DT_NULL => break,
+                DT_STRTAB => string_table = Some(value),
+                DT_NEEDED | DT_RPATH | DT_RUNPATH => entries.push((tag, value)),
+                _ => {}
+            }
+        }
+        // DT_STRTAB is an address, which the loaded segments map to an offset in the file.
+        let string_table = string_table?;
+        let string_table = loads
+            .iter()
+            .find(|(_, address, size)| {
+                *address <= string_table
+                    && address
+                        .checked_add(*size)
+                        .is_some_and(|end| string_table < end)
+            })
+            .and_then(|(offset, address, _)| {
+                string_table.checked_sub(*address)?.checked_add(*offset)
+            })?;
+        for (tag, value) in entries {
+            let string = reader.string(string_table.checked_add(value)?)?;
+            match tag {
+                DT_NEEDED => elf_file.needed.push(string),
+                DT_RPATH => elf_file.rpath.extend(ElfFile::search_path(&string)),
+                _ => elf_file.runpath.extend(ElfFile::search_path(&string)),
+            }
+        }
+        Some(elf_file)
+    }
+
+    fn reader(data: &[u8]) -> Option<Reader<'_>> {
+        if !data.starts_with(ELF_MAGIC) {
+            return None;
+        }
+        Some(Reader {
+            data,
+            is_64_bit: *data.get(4)? == ELFCLASS64,
+            is_big_endian: *data.get(5)? == ELFDATA2MSB,
+        })
+    }
+
+    fn search_path(string: &str) -> impl Iterator<Item = String> + '_ {
+        string
+            .split(':')
+            .filter(|directory| !directory.is_empty())
+            .map(String::from)
+    }
+}
+
+/// Returns the libraries in the contents of `/etc/ld.so.cache` as pairs of names, such as
+/// `libc.so.6`, and paths, such as `/lib/x86_64-linux-gnu/libc.so.6`, in the order of the cache.
+///
+/// The cache is in the byte order of the host which wrote it.  Both the format of glibc 2.32 and
+/// later and the older format, optionally followed by the newer one, are read.  Returns nothing if
+/// the cache is malformed.
+pub(crate) fn ld_so_cache_libraries(data: &[u8]) -> Vec<(String, String)> {
+    let reader = Reader {
+        data,
+        is_64_bit: false,
+        is_big_endian: cfg!(target_endian = "big"),
+    };
+    let string_at = |base: u64, offset: u32| reader.string(base + u64::from(offset));
+    let mut libraries = Vec::new();
+
+    let mut new_format = 0;
+    if data.starts_with(LD_SO_CACHE_OLD_MAGIC) {
+        let count = match reader.u32(12) {
+            Some(count) => u64::from(count),
+            None => return libraries,
+        };
+        let strings = 16 + count * 12;
+        // The newer format follows, aligned to 8 bytes.
+        new_format = (strings + 7) & !7;
+        if !data[new_format.min(data.len() as u64) as usize..].starts_with(LD_SO_CACHE_NEW_MAGIC) {
+            for entry in (0..count).map(|index| 16 + index * 12) {
+                match (
+                    reader
+                        .u32(entry + 4)
+                        .and_then(|key| string_at(strings, key)),
+                    reader
+                        .u32(entry + 8)
+                        .and_then(|value| string_at(strings, value)),
+                ) {
+                    (Some(name), Some(path)) => libraries.push((name, path)),
+                    _ => return Vec::new(),
+                }
+            }
+            return libraries;
+        }
+    }
+    if !data[new_format.min(data.len() as u64) as usize..].starts_with(LD_SO_CACHE_NEW_MAGIC) {
+        return libraries;
+    }
+    let count = match reader.u32(new_format + 20) {
+        Some(count) => u64::from(count),
+        None => return libraries,
+    };
+    for entry in (0..count).map(|index| new_format + 48 + index * 24) {
+        match (
+            reader
+                .u32(entry + 4)
+                .and_then(|key| string_at(new_format, key)),
+            reader
+                .u32(entry + 8)
+                .and_then(|value| string_at(new_format, value)),
+        ) {
+            (Some(name), Some(path)) => libraries.push((name, path)),
+            _ => return Vec::new(),
+        }
+    }
+    libraries
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::files_list::tests::test_elf::{elf_file, ld_so_cache};
+
+    #[test]
+    fn elf_file_parse() {
+        let data = elf_file(
+            Some("/lib64/ld-linux-x86-64.so.2"),
+            &["libz.so.1", "libc.so.6"],
+            Some("$ORIGIN/../lib:/opt/lib"),
+        );
+        assert_eq!(
+            Some(ElfFile {
+                interpreter: Some("/lib64/ld-linux-x86-64.so.2".to_string()),
+                needed: vec!["libz.so.1".to_string(), "libc.so.6".to_string()],
+                rpath: Vec::new(),
+                runpath: vec!["$ORIGIN/../lib".to_string(), "/opt/lib".to_string()],
+            }),
+            ElfFile::parse(&data)
+        );
+        assert_eq!(
+            Some(ElfIdentity {
+                is_64_bit: true,
+                machine: 62
+            }),
+            ElfIdentity::parse(&data[..64])
+        );
+    }
+
+    #[test]
+    fn elf_file_parse_rejects_other_files() {
+        assert_eq!(None, ElfFile::parse(b"#!/bin/sh\n"));
+        assert_eq!(
+            None,
+            ElfFile::parse(&elf_file(None, &["libc.so.6"], None)[..100])
+        );
+        assert_eq!(None, ElfIdentity::parse(b"\x7fEL"));
+    }
+
+    #[test]
+    fn elf_file_parse_rejects_overflowing_segments() {
+        let data = elf_file(None, &["libc.so.6"], None);
+        // The size and then the offset of the loaded segment, which maps the string table.
+        for field in [64 + 32, 64 + 8] {
+            let mut data = data.clone();
+            data[field..field + 8].copy_from_slice(&u64::MAX.to_le_bytes());
+            assert_eq!(None, ElfFile::parse(&data));
+        }
+    }
+
+    #[test]
+    fn ld_so_cache_libraries_of_cache() {
+        let data = ld_so_cache(&[
+            ("libc.so.6", "/lib/x86_64-linux-gnu/libc.so.6"),
+            ("libz.so.1", "/lib/x86_64-linux-gnu/libz.so.1"),
+        ]);
+        assert_eq!(
+            vec![
+                (
+                    "libc.so.6".to_string(),
+                    "/lib/x86_64-linux-gnu/libc.so.6".to_string()
+                ),
+                (
+                    "libz.so.1".to_string(),
+                    "/lib/x86_64-linux-gnu/libz.so.1".to_string()
+                ),
+            ],
+            ld_so_cache_libraries(&data)
+        );
+        assert!(ld_so_cache_libraries(b"not a cache").is_empty());
+        assert!(ld_so_cache_libraries(&data[..60]).is_empty());
+    }
+}
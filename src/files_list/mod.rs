@@ -2,106 +2,455 @@
 //!
 //! `files_list` helps build and maintain the sandstorm-files.list file
 
-use std::collections::BTreeSet;
+use std::collections::btree_map;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fs::{File, OpenOptions};
-use std::io::BufReader;
+use std::io::SeekFrom::Start;
+use std::io::{BufReader, Seek};
 use std::path::{self, Path, PathBuf};
 
 use crate::error::Error;
+use crate::pkgdef::SourceMap;
 
-const DOT_CPYTHON_DASH: &str = ".cpython-";
-const PYC_EXTENSION: &str = ".pyc";
-const PYCACHE_DIRECTORY: &str = "__pycache__";
+mod always_include;
+mod bytecode;
+mod directories;
+mod elf;
+mod pack;
+mod package;
+mod prune;
+mod python_bytecode;
+mod python_distributions;
+mod python_imports;
+mod python_package_data;
+mod python_virtualenv;
+mod shared_libraries;
+mod symlinks;
+
+pub use directories::{EntryKind, ListedDirectory, LARGE_UNTRACKED_FILES};
+pub use package::PackageContents;
+pub use python_bytecode::PythonBytecodeFiles;
+pub use python_package_data::DEFAULT_PACKAGE_DATA_PATTERNS;
+pub use shared_libraries::SharedLibraryFiles;
+
+use bytecode::{legacy_bytecode_source, CachedBytecode, PYCACHE_DIRECTORY, PYC_EXTENSION};
 
 pub struct FilesList {
     filepath: PathBuf,
+    root: PathBuf,
+    implementation_tags: Option<Vec<String>>,
+    /// `true` if `load` creates the files list file when it does not exist.
+    create: bool,
+    /// The source map of the package definition and the directory of the package definition.
+    source_map: Option<(SourceMap, PathBuf)>,
+    /// The entries which were not added because the source map hides them or does not find them.
+    unreachable: BTreeSet<String>,
     file: Option<File>,
     headers: Vec<String>,
-    listed_files: BTreeSet<String>,
+    listed_files: BTreeMap<String, Vec<String>>,
+    trailers: Vec<String>,
 }
 
 impl FilesList {
     /// Constructs a new `FilesList` at path `filepath`.
+    ///
+    /// Entries in the files list are resolved against the filesystem root, `/`.
     pub fn new(filepath: &Path) -> Self {
+        FilesList::with_root(filepath, Path::new("/"))
+    }
+
+    /// Constructs a new `FilesList` at path `filepath` with entries resolved against `root`.
+    ///
+    /// `root` is the directory which will become the root of the package, such as a chroot or a
+    /// container's root filesystem.  Entries in the files list remain relative to the root of the
+    /// package.
+    pub fn with_root(filepath: &Path, root: &Path) -> Self {
         Self {
             filepath: filepath.to_path_buf(),
+            root: root.to_path_buf(),
+            implementation_tags: None,
+            create: true,
+            source_map: None,
+            unreachable: BTreeSet::new(),
             file: None,
             headers: Vec::new(),
-            listed_files: BTreeSet::new(),
+            listed_files: BTreeMap::new(),
+            trailers: Vec::new(),
         }
     }
 
-    /// Adds Python source files to the files list file.
+    /// Returns the directory against which entries in the files list are resolved.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Restricts the Python implementations whose bytecode files are recognized.
     ///
-    /// `include_python_source_files` reads the files list file and identifies Python bytecode
-    /// files.  If the corresponding Python source files are present on the filesystem and are not
-    /// listed in the files list file, this function adds them to the files list file.
+    /// A bytecode file such as `__pycache__/views.pypy39.pyc` is recognized when its cache tag,
+    /// `pypy39`, starts with one of `implementation_tags`, such as `pypy`.  By default, bytecode
+    /// files with any cache tag are recognized.
+    pub fn set_implementation_tags<S: AsRef<str>>(&mut self, implementation_tags: &[S]) {
+        self.implementation_tags = Some(
+            implementation_tags
+                .iter()
+                .map(|tag| tag.as_ref().to_string())
+                .collect(),
+        );
+    }
+
+    /// Sets whether `load` creates the files list file when it does not exist, which it does by
+    /// default.
     ///
-    /// Leading comments in the files list file will be preserved.  All other comments will be lost.
-    pub fn include_python_source_files(&mut self) -> Result<BTreeSet<String>, Error> {
-        self.ingest_file()?;
-        let added_sources = self.add_missing_python_source_files()?;
-        self.write_file()?;
-        Ok(added_sources)
+    /// Without `create`, `load` returns `Error::FailedToOpenFile` for a files list file which does
+    /// not exist, so that nothing is written to the filesystem.
+    pub fn set_create(&mut self, create: bool) {
+        self.create = create;
     }
 
-    fn add_missing_python_source_files(&mut self) -> Result<BTreeSet<String>, Error> {
-        let mut added_sources: BTreeSet<String> = BTreeSet::new();
+    /// Restricts the entries which are added to those `spk` can include with `source_map`.
+    ///
+    /// Once a source map is set, the operations which add entries skip each entry which the
+    /// `searchPath` of the source map hides with `hidePaths` or does not find, and report it in
+    /// `unreachable_entries`.  Relative source paths are resolved against `pkgdef_directory`, the
+    /// directory of the package definition file, and absolute source paths beneath the root.
+    pub fn set_source_map(&mut self, source_map: SourceMap, pkgdef_directory: &Path) {
+        self.source_map = Some((source_map, pkgdef_directory.to_path_buf()));
+    }
 
-        for line in self.listed_files.iter() {
-            if line.contains(PYCACHE_DIRECTORY) && line.ends_with(PYC_EXTENSION) {
-                let possible_sources = FilesList::suggest_python_sources_for(line.as_str())?;
-                for possible_source in possible_sources {
-                    let mut python_source = PathBuf::new();
-                    python_source.push("/");
-                    python_source.push(possible_source.clone());
-                    if python_source.is_file() {
-                        added_sources.insert(possible_source);
-                    }
-                }
-            }
-        }
-        for line in added_sources.iter() {
-            self.listed_files.insert((line[1..]).to_string());
+    /// Returns the entries which were not added since the files list file was read because the
+    /// source map set by `set_source_map` hides them or does not find them, with a leading `/`.
+    pub fn unreachable_entries(&self) -> &BTreeSet<String> {
+        &self.unreachable
+    }
+
+    /// Returns the cache tags of the Python bytecode files in the files list, such as
+    /// `cpython-39`.
+    pub fn cache_tags(&self) -> BTreeSet<String> {
+        self.cached_bytecode()
+            .map(|bytecode| bytecode.cache_tag.to_string())
+            .collect()
+    }
+
+    /// Returns the number of Python bytecode files in the files list at each optimization level.
+    ///
+    /// The optimization level is `None` for bytecode compiled without optimization, `Some("1")` for
+    /// bytecode compiled with `-O` and `Some("2")` for bytecode compiled with `-OO`.
+    pub fn optimization_levels(&self) -> BTreeMap<Option<String>, usize> {
+        let mut optimization_levels = BTreeMap::new();
+        for bytecode in self.cached_bytecode() {
+            *optimization_levels
+                .entry(bytecode.optimization.map(String::from))
+                .or_insert(0) += 1;
         }
+        optimization_levels
+    }
 
-        Ok(added_sources)
+    /// Reads the files list file.
+    ///
+    /// `load` opens the files list file, creating it if it does not exist, and takes an exclusive
+    /// lock on it.  The lock is held until the `FilesList` is dropped.  Any entries read by a
+    /// previous call to `load` are discarded.
+    ///
+    /// Each `include_*` and `exclude_*` method calls `load`, changes the files list as its
+    /// counterpart which does not write the files list file describes, such as
+    /// `add_symlink_targets` for `include_symlink_targets`, and calls `save`.
+    ///
+    /// Comments and blank lines are not entries.  Comments and blank lines before the first entry
+    /// are headers, except that comments after the last blank line of the headers belong to the
+    /// first entry.  All other comments and blank lines belong to the entry which follows them, so
+    /// they are kept with that entry when the files list is sorted and saved.
+    pub fn load(&mut self) -> Result<(), Error> {
+        self.load_file(self.create)
     }
 
-    fn ingest_file(&mut self) -> Result<(), Error> {
+    /// Reads the files list file as `load` does, creating it only if `create` is `true`.
+    fn load_file(&mut self, create: bool) -> Result<(), Error> {
         use std::io::BufRead;
 
         let mut in_headers: bool = true;
+        let mut annotations: Vec<String> = Vec::new();
 
-        self.open_and_lock_file()?;
+        self.headers.clear();
+        self.listed_files.clear();
+        self.trailers.clear();
+        self.unreachable.clear();
+        self.open_and_lock_file(create)?;
         let mut file = self.file.take().expect("Unable to read the FilesList file");
         {
+            file.seek(Start(0))?;
             let reader = BufReader::new(&mut file);
             for reader_line in reader.lines() {
                 let line = reader_line?;
+                if FilesList::is_annotation(&line) {
+                    annotations.push(line);
+                    continue;
+                }
                 if in_headers {
-                    if line.starts_with('#') {
-                        self.headers.push(line);
-                    } else {
-                        in_headers = false;
-                        self.listed_files.insert(line);
+                    in_headers = false;
+                    let header_count = annotations
+                        .iter()
+                        .rposition(|annotation| annotation.trim().is_empty())
+                        .map_or(annotations.len(), |index| index + 1);
+                    self.headers = annotations.drain(..header_count).collect();
+                }
+                match self.listed_files.entry(line) {
+                    btree_map::Entry::Occupied(mut entry) => {
+                        entry.get_mut().append(&mut annotations);
+                    }
+                    btree_map::Entry::Vacant(entry) => {
+                        entry.insert(std::mem::take(&mut annotations));
                     }
-                } else {
-                    self.listed_files.insert(line);
                 }
             }
         }
+        if in_headers {
+            self.headers = annotations;
+        } else {
+            self.trailers = annotations;
+        }
         self.file = Some(file);
         Ok(())
     }
-    fn open_and_lock_file(&mut self) -> Result<(), Error> {
-        use fs3::FileExt;
 
+    /// Returns an iterator over the entries in the files list, in sorted order.
+    pub fn entries(&self) -> impl Iterator<Item = &str> {
+        self.listed_files.keys().map(String::as_str)
+    }
+
+    /// Returns an iterator over the lines which `save` would write to the files list file.
+    pub fn lines(&self) -> impl Iterator<Item = &str> {
+        let entries = self.listed_files.iter().flat_map(|(entry, annotations)| {
+            annotations
+                .iter()
+                .map(String::as_str)
+                .chain(std::iter::once(entry.as_str()))
+        });
+        self.headers
+            .iter()
+            .map(String::as_str)
+            .chain(entries)
+            .chain(self.trailers.iter().map(String::as_str))
+    }
+
+    /// Returns `true` if `entry` is listed in the files list.
+    pub fn contains(&self, entry: &str) -> bool {
+        self.listed_files
+            .contains_key(FilesList::normalize_entry(entry))
+    }
+
+    /// Adds `entry` to the files list.
+    ///
+    /// Entries in the files list are relative to the root of the package, so a leading `/` is
+    /// removed from `entry`.  Returns `true` if `entry` was not already listed.
+    pub fn insert(&mut self, entry: &str) -> bool {
+        match self
+            .listed_files
+            .entry(FilesList::normalize_entry(entry).to_string())
+        {
+            btree_map::Entry::Occupied(_) => false,
+            btree_map::Entry::Vacant(entry) => {
+                entry.insert(Vec::new());
+                true
+            }
+        }
+    }
+
+    /// Removes `entry` from the files list.
+    ///
+    /// Comments and blank lines which belonged to `entry` are kept with the entry which follows
+    /// it.  Returns `true` if `entry` was listed.
+    pub fn remove(&mut self, entry: &str) -> bool {
+        use std::ops::Bound::{Excluded, Unbounded};
+
+        let entry = FilesList::normalize_entry(entry);
+        let mut annotations = match self.listed_files.remove(entry) {
+            Some(annotations) => annotations,
+            None => return false,
+        };
+        let following = match self
+            .listed_files
+            .range_mut::<str, _>((Excluded(entry), Unbounded))
+            .next()
+        {
+            Some((_, following)) => following,
+            None => &mut self.trailers,
+        };
+        annotations.append(following);
+        *following = annotations;
+        true
+    }
+
+    /// Adds `entry` to the files list, as `insert` does, if the source map set by
+    /// `set_source_map` finds it.
+    ///
+    /// Otherwise `entry` is reported by `unreachable_entries`.  Returns `true` if `entry` was
+    /// added.
+    fn insert_reachable(&mut self, entry: &str) -> Result<bool, Error> {
+        if self.contains(entry) {
+            return Ok(false);
+        }
+        if !self.is_reachable(entry)? {
+            self.unreachable
+                .insert(format!("/{}", FilesList::normalize_entry(entry)));
+            return Ok(false);
+        }
+        Ok(self.insert(entry))
+    }
+
+    /// Adds `entries` to the files list as `insert_reachable` does, removing the entries which
+    /// were not added from `entries`.
+    fn insert_reachable_entries(&mut self, entries: &mut BTreeSet<String>) -> Result<(), Error> {
+        let mut added = BTreeSet::new();
+        for entry in std::mem::take(entries) {
+            if self.insert_reachable(&entry)? {
+                added.insert(entry);
+            }
+        }
+        *entries = added;
+        Ok(())
+    }
+
+    /// Writes the files list file.
+    ///
+    /// `save` replaces the contents of the files list file with the headers read by `load`
+    /// followed by the entries of the files list, in sorted order, each preceded by its comments.
+    ///
+    /// The new contents are written to a temporary file beside the files list file, which is
+    /// synchronized to disk and then renamed over the files list file while the exclusive lock is
+    /// held.  If `save` returns an `Error`, the files list file is unchanged.
+    ///
+    /// `save` fails with `Error::FilesListNotLoaded` unless `load` has read the files list file,
+    /// so that a files list which was never read does not replace the entries of the file.
+    pub fn save(&mut self) -> Result<(), Error> {
         if self.file.is_none() {
+            return Err(Error::FilesListNotLoaded(
+                self.filepath.to_string_lossy().to_string(),
+            ));
+        }
+        let temporary_filepath = self.temporary_filepath();
+        // Only the holder of the lock writes the temporary file, so a temporary file left behind
+        // by a process which failed is stale.
+        match std::fs::remove_file(temporary_filepath.as_path()) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+                return Err(Error::FailedToOpenFile(
+                    temporary_filepath.to_string_lossy().to_string(),
+                    Some(err),
+                ));
+            }
+            _ => {}
+        }
+        let temporary_file = match OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(temporary_filepath.as_path())
+        {
+            Ok(file) => file,
+            Err(err) => {
+                return Err(Error::FailedToOpenFile(
+                    temporary_filepath.to_string_lossy().to_string(),
+                    Some(err),
+                ));
+            }
+        };
+        if let Err(err) = self.replace_file(&temporary_file, temporary_filepath.as_path()) {
+            drop(temporary_file);
+            // The rename did not happen, so the temporary file is ours to remove.
+            let _ = std::fs::remove_file(temporary_filepath.as_path());
+            return Err(err);
+        }
+        // The temporary file is now the files list file and it is already locked.  Dropping the
+        // old file releases the lock on the replaced file.
+        self.file = Some(temporary_file);
+        // Synchronize the directory so that the rename itself is durable.
+        if let Some(parent) = self.directory() {
+            File::open(parent)?.sync_all()?;
+        }
+        Ok(())
+    }
+
+    /// Adds Python source files to the files list file.
+    ///
+    /// `include_python_source_files` reads the files list file and identifies Python bytecode
+    /// files.  If the corresponding Python source files are present on the filesystem and are not
+    /// listed in the files list file, this function adds them to the files list file.  The added
+    /// files are returned with a leading `/`; files which were already listed are not.
+    ///
+    /// Comments in the files list file will be preserved.
+    pub fn include_python_source_files(&mut self) -> Result<BTreeSet<String>, Error> {
+        self.load()?;
+        let added_sources = self.add_python_source_files()?;
+        self.save()?;
+        Ok(added_sources)
+    }
+
+    /// Checks the files list file for missing Python source files without writing it.
+    ///
+    /// `check_python_source_files` reads the files list file and returns the Python source files
+    /// which `include_python_source_files` would add, with a leading `/`.  An empty result means
+    /// that the files list is complete.  A files list file which does not exist is not created
+    /// and `Error::FailedToOpenFile` is returned.
+    pub fn check_python_source_files(&mut self) -> Result<BTreeSet<String>, Error> {
+        self.load_file(false)?;
+        self.missing_python_source_files()
+    }
+
+    /// Adds Python source files to the files list without writing the files list file.
+    ///
+    /// For each Python bytecode file in the files list, `add_python_source_files` adds the
+    /// corresponding Python source file if it is present beneath the root and is not listed.  The
+    /// added files are returned with a leading `/`.
+    ///
+    /// Bytecode files in `__pycache__` directories and legacy bytecode files beside their source
+    /// files, such as `module.pyc` and `module.pyo` from Python 2 or `compileall -b`, are both
+    /// recognized.
+    pub fn add_python_source_files(&mut self) -> Result<BTreeSet<String>, Error> {
+        let mut added_sources = self.missing_python_source_files()?;
+        self.insert_reachable_entries(&mut added_sources)?;
+
+        Ok(added_sources)
+    }
+
+    fn missing_python_source_files(&self) -> Result<BTreeSet<String>, Error> {
+        let mut missing_sources: BTreeSet<String> = BTreeSet::new();
+
+        for line in self.listed_files.keys() {
+            let possible_sources = if line.contains(PYCACHE_DIRECTORY) {
+                if !line.ends_with(PYC_EXTENSION) {
+                    continue;
+                }
+                self.suggest_python_sources_for(line.as_str())?
+            } else {
+                match legacy_bytecode_source(line.as_str()) {
+                    Some(possible_source) => vec![format!("/{}", possible_source)],
+                    None => continue,
+                }
+            };
+            for possible_source in possible_sources {
+                if !self.contains(&possible_source) && self.resolve(&possible_source).is_file() {
+                    missing_sources.insert(possible_source);
+                }
+            }
+        }
+
+        Ok(missing_sources)
+    }
+
+    /// Opens and exclusively locks the files list file, unless it is already open.
+    ///
+    /// `save` renames a new file over the files list file, so a process which was waiting for the
+    /// lock may get it on the replaced file.  The lock is only kept once the locked file is still
+    /// the one at the path of the files list file.
+    fn open_and_lock_file(&mut self, create: bool) -> Result<(), Error> {
+        use fs3::FileExt;
+        use std::os::unix::fs::MetadataExt;
+
+        while self.file.is_none() {
             let file = match OpenOptions::new()
                 .read(true)
                 .write(true)
-                .create(true)
+                .create(create)
+                .truncate(false)
                 .open(self.filepath.as_path())
             {
                 Ok(file) => file,
@@ -113,29 +462,183 @@ impl FilesList {
                 }
             };
             file.lock_exclusive()?;
-            self.file = Some(file);
+            let locked = file.metadata()?;
+            match std::fs::metadata(self.filepath.as_path()) {
+                Ok(current) if current.dev() == locked.dev() && current.ino() == locked.ino() => {
+                    self.file = Some(file);
+                }
+                Ok(_) => continue,
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(err) => return Err(err.into()),
+            }
         }
         Ok(())
     }
 
-    fn suggest_python_sources_for(pyc_path: &str) -> Result<Vec<String>, Error> {
+    fn replace_file(&self, temporary_file: &File, temporary_filepath: &Path) -> Result<(), Error> {
+        use fs3::FileExt;
+        use std::io::{BufWriter, Write};
+
+        temporary_file.lock_exclusive()?;
+        let mut writer = BufWriter::new(temporary_file);
+        for line in self.lines() {
+            self.write_line(&mut writer, line)?;
+        }
+        writer.flush()?;
+        drop(writer);
+        if let Some(file) = &self.file {
+            temporary_file.set_permissions(file.metadata()?.permissions())?;
+        }
+        temporary_file.sync_all()?;
+        std::fs::rename(temporary_filepath, self.filepath.as_path())?;
+        Ok(())
+    }
+
+    fn write_line<W: std::io::Write>(&self, writer: &mut W, line: &str) -> Result<(), Error> {
+        let mut write_count: usize;
+
+        write_count = writer.write(line.as_bytes())?;
+        if write_count < line.len() {
+            return Err(Error::FilesListWriteIncomplete(
+                self.filepath.to_string_lossy().to_string(),
+            ));
+        }
+        write_count = writer.write(&[0x0a])?;
+        if write_count < 1 {
+            return Err(Error::FilesListWriteIncomplete(
+                self.filepath.to_string_lossy().to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Returns the directory containing the files list file.
+    fn directory(&self) -> Option<&Path> {
+        match self.filepath.parent() {
+            Some(parent) if parent.as_os_str().is_empty() => Some(Path::new(".")),
+            parent => parent,
+        }
+    }
+
+    /// Returns the path of the temporary file used by `save`.
+    fn temporary_filepath(&self) -> PathBuf {
+        let file_name = match self.filepath.file_name() {
+            Some(file_name) => file_name.to_string_lossy().to_string(),
+            None => "sandstorm-files.list".to_string(),
+        };
+        self.filepath.with_file_name(format!(
+            ".{}.wheelhoss-{}.tmp",
+            file_name,
+            std::process::id()
+        ))
+    }
+
+    /// Returns an iterator over the names of the bytecode files in `__pycache__` directories.
+    fn cached_bytecode(&self) -> impl Iterator<Item = CachedBytecode<'_>> {
+        self.listed_files
+            .keys()
+            .filter(|entry| entry.contains(PYCACHE_DIRECTORY))
+            .filter_map(|entry| entry.rsplit('/').next())
+            .filter_map(CachedBytecode::parse)
+    }
+
+    /// Returns `true` if `line` is a comment or a blank line rather than an entry.
+    fn is_annotation(line: &str) -> bool {
+        line.starts_with('#') || line.trim().is_empty()
+    }
+
+    fn normalize_entry(entry: &str) -> &str {
+        entry.trim_start_matches('/')
+    }
+
+    /// Returns the path of `entry` beneath the root.
+    fn resolve(&self, entry: &str) -> PathBuf {
+        self.root.join(FilesList::normalize_entry(entry))
+    }
+
+    /// Returns the entries for the files and symbolic links beneath the directory `entry`.
+    ///
+    /// Symbolic links to directories are not followed.  The entries are sorted.
+    fn files_beneath(&self, entry: &str) -> Result<BTreeSet<String>, Error> {
+        let mut files = BTreeSet::new();
+        let mut directories = vec![FilesList::normalize_entry(entry).to_string()];
+        while let Some(directory) = directories.pop() {
+            for dir_entry in std::fs::read_dir(self.resolve(&directory))? {
+                let dir_entry = dir_entry?;
+                let name = match dir_entry.file_name().into_string() {
+                    Ok(name) => name,
+                    Err(name) => {
+                        return Err(Error::UnableToProcessNonUtf8Path(
+                            name.to_string_lossy().into_owned(),
+                        ))
+                    }
+                };
+                let child = format!("{}/{}", directory, name);
+                if dir_entry.file_type()?.is_dir() {
+                    directories.push(child);
+                } else {
+                    files.insert(child);
+                }
+            }
+        }
+        Ok(files)
+    }
+
+    /// Returns the sorted names in the directory `entry`, or nothing if it does not exist.
+    fn directory_names(&self, entry: &str) -> Result<Vec<String>, Error> {
+        let read_dir = match std::fs::read_dir(self.resolve(entry)) {
+            Ok(read_dir) => read_dir,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err.into()),
+        };
+        let mut names = Vec::new();
+        for dir_entry in read_dir {
+            names.push(dir_entry?.file_name().to_string_lossy().to_string());
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    /// Removes `.` and `..` components from the relative path `path`.
+    ///
+    /// Returns `None` if `path` would leave the root.
+    fn normalize_path(path: &str) -> Option<String> {
+        let mut components: Vec<&str> = Vec::new();
+        for component in path.split('/') {
+            match component {
+                "" | "." => {}
+                ".." => {
+                    components.pop()?;
+                }
+                component => components.push(component),
+            }
+        }
+        Some(components.join("/"))
+    }
+
+    fn suggest_python_sources_for(&self, pyc_path: &str) -> Result<Vec<String>, Error> {
         let mut result = Vec::<String>::new();
         let mut path = PathBuf::new();
         let mut suggestion = PathBuf::new();
-        path.push(&path::MAIN_SEPARATOR.to_string());
+        path.push(path::MAIN_SEPARATOR.to_string());
         path.push(pyc_path);
         for path_part in path.iter() {
             if let Some(part) = path_part.to_str() {
                 if part.contains(PYCACHE_DIRECTORY) {
                     continue;
-                } else if part.contains(DOT_CPYTHON_DASH) && part.ends_with(PYC_EXTENSION) {
-                    // This is going to need more work when this is used with Pypy or other
-                    // interpreters.
-                    if let Some((left, _)) = part.split_once(DOT_CPYTHON_DASH) {
-                        suggestion.push(format!("{}.py", left));
-                    } else {
-                        return Err(Error::FailedToSplitFilename(part.to_string()));
+                } else if part.ends_with(PYC_EXTENSION) {
+                    // A name without a cache tag, such as `__pycache__/module.pyc`, is not
+                    // cached bytecode of any source file.
+                    let bytecode = match CachedBytecode::parse(part) {
+                        Some(bytecode) => bytecode,
+                        None => return Ok(result),
+                    };
+                    if let Some(implementation_tags) = &self.implementation_tags {
+                        if !bytecode.is_from(implementation_tags) {
+                            return Ok(result);
+                        }
                     }
+                    suggestion.push(format!("{}.py", bytecode.module));
                 } else {
                     suggestion.push(part);
                 }
@@ -148,46 +651,19 @@ impl FilesList {
         result.push(suggestion.to_str().unwrap().to_string());
         Ok(result)
     }
+}
 
-    fn write_file(&mut self) -> Result<(), Error> {
-        use std::io::SeekFrom::Start;
-        use std::io::{Seek, Write};
-
-        let mut file = self.file.take().expect("Unable to read the FilesList file");
-        let mut write_count: usize;
+/// Returns the directory which contains `entry`, or an empty string for the root.
+fn parent_of(entry: &str) -> &str {
+    entry.rsplit_once('/').map_or("", |(parent, _)| parent)
+}
 
-        file.seek(Start(0))?;
-        for header_line in self.headers.iter() {
-            write_count = file.write(header_line.as_bytes())?;
-            if write_count < header_line.len() {
-                return Err(Error::FilesListWriteIncomplete(
-                    self.filepath.to_string_lossy().to_string(),
-                ));
-            }
-            write_count = file.write(&[0x0a])?;
-            if write_count < 1 {
-                return Err(Error::FilesListWriteIncomplete(
-                    self.filepath.to_string_lossy().to_string(),
-                ));
-            }
-        }
-        for file_line in self.listed_files.iter() {
-            write_count = file.write(file_line.as_bytes())?;
-            if write_count < file_line.len() {
-                return Err(Error::FilesListWriteIncomplete(
-                    self.filepath.to_string_lossy().to_string(),
-                ));
-            }
-            write_count = file.write(&[0x0a])?;
-            if write_count < 1 {
-                return Err(Error::FilesListWriteIncomplete(
-                    self.filepath.to_string_lossy().to_string(),
-                ));
-            }
-        }
-        file.flush()?;
-        self.file = Some(file);
-        Ok(())
+/// Returns the entry `name` in the directory `directory`.
+fn join(directory: &str, name: &str) -> String {
+    if directory.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}/{}", directory, name)
     }
 }
 
@@ -197,13 +673,20 @@ mod tests {
 
     use super::*;
 
+    pub(super) mod test_elf;
+    // The test helpers predate the lints of current clippy versions.
+    #[allow(clippy::expect_fun_call, clippy::extra_unused_lifetimes)]
     mod test_file_reader;
+    #[allow(clippy::needless_borrows_for_generic_args)]
     mod test_fixture;
+    #[allow(clippy::needless_borrow)]
     mod test_python_files;
+    pub(super) mod test_tree;
 
     use test_file_reader::FileReader;
     use test_fixture::Fixture;
     use test_python_files::PythonFiles;
+    use test_tree::tree;
 
     /// files_list_include_python_source_files is a complex test for the include_python_source_files
     /// function.  It copies an "input" files list to a temporary directory, copies an "expected"
@@ -229,4 +712,409 @@ mod tests {
             FileReader::new(&files_list_input_file)
         )
     }
+
+    /// files_list_include_python_source_files_optimized is like
+    /// files_list_include_python_source_files, but the bytecode files were compiled with `-O` and
+    /// `-OO`, as described by PEP 488, and some are in namespace packages.
+    #[test]
+    fn files_list_include_python_source_files_optimized() {
+        let files_list_input_file =
+            Fixture::copy("files_list_include_python_source_files_optimized.input");
+        let files_list_expected_file =
+            Fixture::copy("files_list_include_python_source_files_optimized.expected");
+        {
+            let python_files = PythonFiles::new(&files_list_input_file, &files_list_expected_file);
+            python_files.touch_files_and_update_files_lists().unwrap();
+        }
+
+        let mut files_list = FilesList::new(&files_list_input_file);
+        let added = files_list.include_python_source_files().unwrap();
+
+        assert_eq!(10, added.len());
+        assert_eq!(
+            FileReader::new(&files_list_expected_file),
+            FileReader::new(&files_list_input_file)
+        );
+        assert_eq!(
+            vec![
+                (None, 2),
+                (Some("1".to_string()), 5),
+                (Some("2".to_string()), 6)
+            ],
+            files_list
+                .optimization_levels()
+                .into_iter()
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn files_list_include_python_source_files_skips_untagged_bytecode() {
+        let (root, files_list_file) = tree(
+            &["opt/app/main.py", "opt/app/stray.py"],
+            "opt/app/__pycache__/main.cpython-39.pyc\n\
+             opt/app/__pycache__/stray.pyc\n",
+        );
+
+        let mut files_list = FilesList::with_root(&files_list_file, root.path());
+        let added = files_list.include_python_source_files().unwrap();
+
+        assert_eq!(
+            vec!["/opt/app/main.py"],
+            added.into_iter().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn files_list_include_python_source_files_with_root() {
+        let (root, files_list_file) = tree(
+            &[
+                "opt/app/__pycache__/main.cpython-39.pyc",
+                "opt/app/__pycache__/missing.cpython-39.pyc",
+                "opt/app/main.py",
+            ],
+            "opt/app/__pycache__/main.cpython-39.pyc\n\
+             opt/app/__pycache__/missing.cpython-39.pyc\n",
+        );
+
+        let mut files_list = FilesList::with_root(&files_list_file, root.path());
+        let added = files_list.include_python_source_files().unwrap();
+
+        assert_eq!(
+            vec!["/opt/app/main.py"],
+            added.into_iter().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            "opt/app/__pycache__/main.cpython-39.pyc\n\
+             opt/app/__pycache__/missing.cpython-39.pyc\n\
+             opt/app/main.py\n",
+            std::fs::read_to_string(&files_list_file).unwrap()
+        );
+    }
+
+    #[test]
+    fn files_list_check_python_source_files() {
+        let files_list_input_file = Fixture::copy("files_list_include_python_source_files.input");
+        let files_list_expected_file =
+            Fixture::copy("files_list_include_python_source_files.expected");
+        {
+            let python_files = PythonFiles::new(&files_list_input_file, &files_list_expected_file);
+            python_files.touch_files_and_update_files_lists().unwrap();
+        }
+        let original = std::fs::read_to_string(&*files_list_input_file).unwrap();
+
+        let mut files_list = FilesList::new(&files_list_input_file);
+        let missing = files_list.check_python_source_files().unwrap();
+        let expected = std::fs::read_to_string(&*files_list_expected_file).unwrap();
+        let expected_missing: BTreeSet<String> = expected
+            .lines()
+            .filter(|line| !original.lines().any(|original_line| original_line == *line))
+            .map(|line| format!("/{}", line))
+            .collect();
+        assert_eq!(expected_missing, missing);
+        assert_eq!(
+            original,
+            std::fs::read_to_string(&*files_list_input_file).unwrap()
+        );
+
+        let mut files_list = FilesList::new(&files_list_expected_file);
+        assert!(files_list.check_python_source_files().unwrap().is_empty());
+    }
+
+    #[test]
+    fn files_list_check_does_not_create_file() {
+        let directory = tempfile::tempdir().unwrap();
+        let files_list_file = directory.path().join("missing.list");
+        let mut files_list = FilesList::new(&files_list_file);
+        assert!(matches!(
+            files_list.check_python_source_files(),
+            Err(Error::FailedToOpenFile(_, Some(_)))
+        ));
+        files_list.set_create(false);
+        assert!(matches!(
+            files_list.load(),
+            Err(Error::FailedToOpenFile(_, Some(_)))
+        ));
+        assert!(!files_list_file.exists());
+    }
+
+    #[test]
+    fn files_list_save_requires_load() {
+        let (_root, files_list_file) = tree(&[], "bin/sh\n");
+
+        let mut files_list = FilesList::new(&files_list_file);
+        files_list.insert("bin/bash");
+        assert!(matches!(
+            files_list.save(),
+            Err(Error::FilesListNotLoaded(_))
+        ));
+        assert_eq!(
+            "bin/sh\n",
+            std::fs::read_to_string(&files_list_file).unwrap()
+        );
+    }
+
+    #[test]
+    fn files_list_save_leaves_file_untouched_on_error() {
+        let files_list_file = Fixture::copy("files_list_include_python_source_files.input");
+        let original = std::fs::read_to_string(&*files_list_file).unwrap();
+
+        let mut files_list = FilesList::new(&files_list_file);
+        files_list.load().unwrap();
+        files_list.remove("bin/bash");
+        // Occupy the temporary file's path so that it cannot be created.
+        std::fs::create_dir(files_list.temporary_filepath()).unwrap();
+        assert!(matches!(
+            files_list.save(),
+            Err(Error::FailedToOpenFile(_, Some(_)))
+        ));
+        assert_eq!(
+            original,
+            std::fs::read_to_string(&*files_list_file).unwrap()
+        );
+
+        std::fs::remove_dir(files_list.temporary_filepath()).unwrap();
+        files_list.save().unwrap();
+        assert!(!files_list.temporary_filepath().exists());
+        assert_eq!(
+            original.replace("bin/bash\n", ""),
+            std::fs::read_to_string(&*files_list_file).unwrap()
+        );
+    }
+
+    #[test]
+    fn files_list_save_replaces_stale_temporary_file() {
+        let files_list_file = Fixture::copy("files_list_include_python_source_files.input");
+        let original = std::fs::read_to_string(&*files_list_file).unwrap();
+
+        let mut files_list = FilesList::new(&files_list_file);
+        files_list.load().unwrap();
+        std::fs::write(files_list.temporary_filepath(), "stale\n").unwrap();
+        files_list.save().unwrap();
+        assert!(!files_list.temporary_filepath().exists());
+        assert_eq!(
+            original,
+            std::fs::read_to_string(&*files_list_file).unwrap()
+        );
+    }
+
+    #[test]
+    fn files_list_save_keeps_changes_of_waiting_writers() {
+        let (_root, files_list_file) = tree(&[], "bin/sh\n");
+
+        let mut first = FilesList::new(&files_list_file);
+        first.load().unwrap();
+        let waiting = {
+            let files_list_file = files_list_file.clone();
+            std::thread::spawn(move || {
+                // Blocks on the lock of the file which `first` replaces.
+                let mut second = FilesList::new(&files_list_file);
+                second.load().unwrap();
+                second.insert("bin/bash");
+                second.save().unwrap();
+            })
+        };
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        first.insert("bin/dash");
+        first.save().unwrap();
+        drop(first);
+        waiting.join().unwrap();
+
+        assert_eq!(
+            "bin/bash\nbin/dash\nbin/sh\n",
+            std::fs::read_to_string(&files_list_file).unwrap()
+        );
+    }
+
+    #[test]
+    fn files_list_preserves_comments_and_blank_lines() {
+        let (_root, files_list_file) = tree(
+            &[],
+            "# Header\n\
+             \n\
+             # Binaries\n\
+             bin/sh\n\
+             bin/bash\n\
+             \n\
+             # Python application\n\
+             opt/app/main.py\n\
+             # Python standard library\n\
+             opt/python/os.py\n\
+             opt/python/re.py\n\
+             \n\
+             # End\n",
+        );
+
+        let mut files_list = FilesList::new(&files_list_file);
+        files_list.load().unwrap();
+        assert_eq!(
+            vec![
+                "bin/bash",
+                "bin/sh",
+                "opt/app/main.py",
+                "opt/python/os.py",
+                "opt/python/re.py"
+            ],
+            files_list.entries().collect::<Vec<_>>()
+        );
+        files_list.insert("opt/app/views.py");
+        files_list.remove("opt/python/os.py");
+        files_list.save().unwrap();
+
+        assert_eq!(
+            "# Header\n\
+             \n\
+             bin/bash\n\
+             # Binaries\n\
+             bin/sh\n\
+             \n\
+             # Python application\n\
+             opt/app/main.py\n\
+             opt/app/views.py\n\
+             # Python standard library\n\
+             opt/python/re.py\n\
+             \n\
+             # End\n",
+            std::fs::read_to_string(&files_list_file).unwrap()
+        );
+    }
+
+    #[test]
+    fn files_list_add_python_source_files_for_other_implementations() {
+        let (root, files_list_file) = tree(
+            &[
+                "opt/app/cpython.py",
+                "opt/app/graalpy.py",
+                "opt/app/pypy.py",
+            ],
+            "opt/app/__pycache__/cpython.cpython-310.pyc\n\
+             opt/app/__pycache__/graalpy.graalpy231-310.pyc\n\
+             opt/app/__pycache__/pypy.pypy39.pyc\n",
+        );
+
+        let mut files_list = FilesList::with_root(&files_list_file, root.path());
+        files_list.load().unwrap();
+        assert_eq!(
+            vec!["cpython-310", "graalpy231-310", "pypy39"],
+            files_list.cache_tags().into_iter().collect::<Vec<_>>()
+        );
+        files_list.set_implementation_tags(&["pypy", "graalpy"]);
+        assert_eq!(
+            vec!["/opt/app/graalpy.py", "/opt/app/pypy.py"],
+            files_list
+                .check_python_source_files()
+                .unwrap()
+                .into_iter()
+                .collect::<Vec<_>>()
+        );
+
+        drop(files_list);
+
+        let mut files_list = FilesList::with_root(&files_list_file, root.path());
+        assert_eq!(3, files_list.check_python_source_files().unwrap().len());
+    }
+
+    #[test]
+    fn files_list_add_python_source_files_for_legacy_bytecode() {
+        let (root, files_list_file) = tree(
+            &["opt/app/main.py", "opt/app/settings.py", "opt/app/views.py"],
+            "opt/app/main.py\n\
+             opt/app/main.pyc\n\
+             opt/app/missing.pyc\n\
+             opt/app/settings.pyo\n\
+             opt/app/views.pyc\n",
+        );
+
+        let mut files_list = FilesList::with_root(&files_list_file, root.path());
+        files_list.load().unwrap();
+        assert_eq!(
+            vec!["/opt/app/settings.py", "/opt/app/views.py"],
+            files_list
+                .add_python_source_files()
+                .unwrap()
+                .into_iter()
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn files_list_add_skips_unreachable_entries() {
+        use crate::pkgdef::{Mapping, SourceMap};
+
+        let (root, files_list_file) = tree(
+            &[
+                "home/user/__pycache__/notes.cpython-39.pyc",
+                "home/user/notes.py",
+                "opt/app/__pycache__/views.cpython-39.pyc",
+                "opt/app/views.py",
+                "var/secret/__pycache__/key.cpython-39.pyc",
+                "var/secret/key.py",
+            ],
+            "home/user/__pycache__/notes.cpython-39.pyc\n\
+             opt/app/__pycache__/views.cpython-39.pyc\n\
+             var/secret/__pycache__/key.cpython-39.pyc\n",
+        );
+        let source_map = SourceMap {
+            search_path: vec![
+                Mapping {
+                    package_path: "opt".to_string(),
+                    source_path: "/opt".to_string(),
+                    hide_paths: Vec::new(),
+                },
+                Mapping {
+                    package_path: "home".to_string(),
+                    source_path: "/home".to_string(),
+                    hide_paths: vec!["user".to_string()],
+                },
+            ],
+        };
+
+        let mut files_list = FilesList::with_root(&files_list_file, root.path());
+        files_list.set_source_map(source_map, root.path());
+        files_list.load().unwrap();
+        let added = files_list.add_python_source_files().unwrap();
+        assert_eq!(
+            vec!["/opt/app/views.py"],
+            added.into_iter().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec!["/home/user/notes.py", "/var/secret/key.py"],
+            files_list
+                .unreachable_entries()
+                .iter()
+                .map(String::as_str)
+                .collect::<Vec<_>>()
+        );
+        assert!(!files_list.contains("var/secret/key.py"));
+
+        files_list.load().unwrap();
+        assert!(files_list.unreachable_entries().is_empty());
+    }
+
+    #[test]
+    fn files_list_load_modify_save() {
+        let files_list_file = Fixture::copy("files_list_include_python_source_files.input");
+
+        {
+            let mut files_list = FilesList::new(&files_list_file);
+            files_list.load().unwrap();
+            assert!(files_list.contains("bin/bash"));
+            assert!(files_list.contains("/bin/bash"));
+            assert!(!files_list.contains("bin/sh"));
+            assert!(files_list.insert("/bin/sh"));
+            assert!(!files_list.insert("bin/sh"));
+            assert!(files_list.remove("bin/bash"));
+            assert!(!files_list.remove("bin/bash"));
+            files_list.save().unwrap();
+        }
+
+        let mut files_list = FilesList::new(&files_list_file);
+        files_list.load().unwrap();
+        let entries: Vec<&str> = files_list.entries().take(2).collect();
+        assert_eq!(vec!["bin/sh", "etc/ld.so.cache"], entries);
+        assert_eq!(71 - 4, files_list.entries().count());
+        let contents = std::fs::read_to_string(&*files_list_file).unwrap();
+        assert!(contents.starts_with("# *** WARNING: GENERATED FILE ***\n"));
+        assert!(contents.ends_with("usr/share/zoneinfo/UTC\n"));
+    }
 }
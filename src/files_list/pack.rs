@@ -0,0 +1,111 @@
+//! The Sandstorm package made from a `FilesList`
+
+use std::path::Path;
+
+use ed25519_dalek::SigningKey;
+
+use super::{FilesList, PackageContents};
+use crate::error::Error;
+use crate::pkgdef::Pkgdef;
+use crate::spk;
+
+impl FilesList {
+    /// Writes the Sandstorm package `spk_path`, signed with `signing_key`, without `spk`.
+    ///
+    /// The package holds the files, directories and symbolic links of `pack_contents`, which are
+    /// returned.  If `pack_contents` returns an `Error`, nothing is written.  The package is
+    /// written as `spk::create_spk` describes.
+    pub fn pack(
+        &self,
+        pkgdef: &Pkgdef,
+        pkgdef_directory: &Path,
+        signing_key: &SigningKey,
+        spk_path: &Path,
+    ) -> Result<PackageContents, Error> {
+        let contents = self.pack_contents(pkgdef, pkgdef_directory)?;
+        let archive = spk::archive(&contents)?;
+        spk::create_spk(spk_path, &archive, signing_key)?;
+        Ok(contents)
+    }
+
+    /// Returns the contents of the package which `pack` writes, as `package_contents` does,
+    /// without writing it.
+    ///
+    /// If the source map does not find a listed entry or a path in `alwaysInclude`,
+    /// `Error::MissingPackageFiles` is returned, as `spk pack` fails then too.  If the package
+    /// does not hold `sandstorm-manifest`, which Sandstorm needs to install it,
+    /// `Error::NoManifest` is returned.
+    ///
+    /// Unlike `spk pack`, `pack` does not compile the manifest of `pkgdef`: `sandstorm-manifest`
+    /// and any other file `spk` would generate are packed only if the files list or
+    /// `alwaysInclude` holds them.
+    pub fn pack_contents(
+        &self,
+        pkgdef: &Pkgdef,
+        pkgdef_directory: &Path,
+    ) -> Result<PackageContents, Error> {
+        let contents = self.package_contents(pkgdef, pkgdef_directory)?;
+        if !contents.missing.is_empty() {
+            return Err(Error::MissingPackageFiles(contents.missing));
+        }
+        if !contents.files.contains_key(spk::MANIFEST_PATH) {
+            return Err(Error::NoManifest(spk::MANIFEST_PATH.to_string()));
+        }
+        Ok(contents)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::files_list::tests::test_tree::tree;
+
+    #[test]
+    fn files_list_pack() {
+        let (root, files_list_file) = tree(
+            &["opt/app/manage.py", "sandstorm-manifest"],
+            "opt/app/manage.py\nsandstorm-manifest\n",
+        );
+        let spk_file = root.path().join("app.spk");
+        let pkgdef = Pkgdef::parse(
+            "const pkgdef :Spk.PackageDefinition = (\n\
+               sourceMap = (searchPath = [(sourcePath = \"/\")]),\n\
+               alwaysInclude = [\"opt/app/static\"]\n\
+             );\n",
+        )
+        .unwrap();
+        let signing_key = ed25519_dalek::SigningKey::from_bytes(&[7; 32]);
+
+        let mut files_list = FilesList::with_root(&files_list_file, root.path());
+        files_list.load().unwrap();
+        match files_list.pack(&pkgdef, root.path(), &signing_key, &spk_file) {
+            Err(Error::MissingPackageFiles(missing)) => {
+                assert_eq!(BTreeSet::from(["/opt/app/static".to_string()]), missing)
+            }
+            result => panic!("unexpected result: {:?}", result),
+        }
+        assert!(!spk_file.exists());
+
+        std::fs::create_dir(root.path().join("opt/app/static")).unwrap();
+        let contents = files_list
+            .pack(&pkgdef, root.path(), &signing_key, &spk_file)
+            .unwrap();
+        assert_eq!(
+            vec!["/opt/app/manage.py", "/sandstorm-manifest"],
+            contents.files.keys().collect::<Vec<_>>()
+        );
+        let spk = std::fs::read(&spk_file).unwrap();
+        assert_eq!(crate::spk::MAGIC_NUMBER, spk[..8]);
+
+        files_list.remove("sandstorm-manifest");
+        assert!(matches!(
+            files_list.pack(&pkgdef, root.path(), &signing_key, &spk_file),
+            Err(Error::NoManifest(_))
+        ));
+        assert_eq!(spk, std::fs::read(&spk_file).unwrap());
+    }
+}
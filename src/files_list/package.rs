@@ -0,0 +1,358 @@
+//! The contents of the Sandstorm package made from a `FilesList`
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::Metadata;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use super::{join, FilesList};
+use crate::error::Error;
+use crate::pkgdef::{Mapping, Pkgdef, SourceMap};
+
+/// The result of `FilesList::package_contents`.
+///
+/// Paths in the package have a leading `/`.
+#[derive(Debug, Default, PartialEq)]
+pub struct PackageContents {
+    /// The regular files, with the paths on the host they are read from.
+    pub files: BTreeMap<String, PathBuf>,
+    pub directories: BTreeSet<String>,
+    /// The symbolic links, with their targets.
+    pub symlinks: BTreeMap<String, String>,
+    /// The listed entries and `alwaysInclude` paths which the source map does not find, for
+    /// which `spk pack` fails.
+    pub missing: BTreeSet<String>,
+}
+
+/// Where the source map finds a path in the package.
+enum Source {
+    Host(PathBuf, Metadata),
+    /// A directory which holds the `packagePath` of a mapping but is not on the host.
+    Virtual,
+}
+
+/// The source map of a package definition, with relative source paths resolved against the
+/// directory of the package definition and absolute ones against the root.
+pub(crate) struct HostSourceMap<'a> {
+    pub source_map: &'a SourceMap,
+    pub pkgdef_directory: &'a Path,
+}
+
+impl FilesList {
+    /// Returns the files, directories and symbolic links which `spk pack` would put in the package.
+    ///
+    /// Each listed entry and each path in `alwaysInclude` is looked up in the `sourceMap` of
+    /// `pkgdef` as `spk` does: the mappings of `searchPath` are tried in order and the first one
+    /// which holds the path, and does not hide it with `hidePaths`, provides it.  Directories
+    /// include everything beneath them, merged from every mapping which holds them, and the
+    /// parent directories of every path are included.  Symbolic links are included as links.
+    ///
+    /// Relative source paths are resolved against `pkgdef_directory`, the directory of the
+    /// package definition file, and absolute source paths beneath the root.  Files which `spk`
+    /// generates, such as `sandstorm-manifest`, are not included.
+    ///
+    /// A path in `alwaysInclude` which leaves the package, such as `..`, is missing.
+    pub fn package_contents(
+        &self,
+        pkgdef: &Pkgdef,
+        pkgdef_directory: &Path,
+    ) -> Result<PackageContents, Error> {
+        let source_map = HostSourceMap {
+            source_map: &pkgdef.source_map,
+            pkgdef_directory,
+        };
+        let mut contents = PackageContents::default();
+        let mut expanded: BTreeSet<String> = BTreeSet::new();
+        let mut entries: Vec<String> = self.entries().map(String::from).collect();
+        for path in pkgdef.always_include.iter() {
+            match FilesList::normalize_path(path) {
+                Some(entry) => entries.push(entry),
+                // No mapping holds a path which leaves the package, such as `..`.
+                None => {
+                    contents
+                        .missing
+                        .insert(format!("/{}", path.trim_start_matches('/')));
+                }
+            }
+        }
+        for entry in entries {
+            match self.source_of(&source_map, &entry)? {
+                Some(source) => {
+                    let mut parent = entry.as_str();
+                    while let Some((directory, _)) = parent.rsplit_once('/') {
+                        contents.directories.insert(format!("/{}", directory));
+                        parent = directory;
+                    }
+                    self.add_package_tree(
+                        &source_map,
+                        entry,
+                        source,
+                        &mut contents,
+                        &mut expanded,
+                    )?;
+                }
+                None => {
+                    contents.missing.insert(format!("/{}", entry));
+                }
+            }
+        }
+
+        Ok(contents)
+    }
+
+    /// Adds `entry` and, if it is a directory, everything beneath it to `contents`.
+    ///
+    /// `expanded` holds the directories whose contents were already added.
+    fn add_package_tree(
+        &self,
+        source_map: &HostSourceMap,
+        entry: String,
+        source: Source,
+        contents: &mut PackageContents,
+        expanded: &mut BTreeSet<String>,
+    ) -> Result<(), Error> {
+        let path = format!("/{}", entry);
+        match source {
+            Source::Host(host_path, metadata) if metadata.file_type().is_symlink() => {
+                let target = std::fs::read_link(&host_path)?;
+                contents
+                    .symlinks
+                    .insert(path, target.to_string_lossy().into_owned());
+            }
+            Source::Host(host_path, metadata) if !metadata.is_dir() => {
+                contents.files.insert(path, host_path);
+            }
+            _ => {
+                if !expanded.insert(entry.clone()) {
+                    return Ok(());
+                }
+                if !entry.is_empty() {
+                    contents.directories.insert(path);
+                }
+                for name in self.package_directory_names(source_map, &entry)? {
+                    let child = join(&entry, &name);
+                    if let Some(source) = self.source_of(source_map, &child)? {
+                        self.add_package_tree(source_map, child, source, contents, expanded)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns `true` if the source map set by `set_source_map` finds `entry`, or if no source
+    /// map is set.
+    pub(crate) fn is_reachable(&self, entry: &str) -> Result<bool, Error> {
+        let (source_map, pkgdef_directory) = match &self.source_map {
+            Some((source_map, pkgdef_directory)) => (source_map, pkgdef_directory),
+            None => return Ok(true),
+        };
+        let source_map = HostSourceMap {
+            source_map,
+            pkgdef_directory,
+        };
+        match FilesList::normalize_path(entry) {
+            Some(entry) => Ok(self.source_of(&source_map, &entry)?.is_some()),
+            None => Ok(false),
+        }
+    }
+
+    /// Returns where the first mapping which holds `entry` finds it, or `None` if no mapping
+    /// does.
+    fn source_of(&self, source_map: &HostSourceMap, entry: &str) -> Result<Option<Source>, Error> {
+        let mut is_virtual = entry.is_empty();
+        for mapping in source_map.source_map.search_path.iter() {
+            let package_path = mapping.package_path.trim_matches('/');
+            let rest = match relative_path(package_path, entry) {
+                Some(rest) => rest,
+                None => {
+                    is_virtual |= relative_path(entry, package_path).is_some();
+                    continue;
+                }
+            };
+            if is_hidden(mapping, rest) {
+                continue;
+            }
+            let host_path = source_map.host_path(self, mapping, rest);
+            match host_path.symlink_metadata() {
+                Ok(metadata) => return Ok(Some(Source::Host(host_path, metadata))),
+                Err(err)
+                    if matches!(err.kind(), ErrorKind::NotFound | ErrorKind::NotADirectory) => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+        Ok(if is_virtual {
+            Some(Source::Virtual)
+        } else {
+            None
+        })
+    }
+
+    /// Returns the sorted names in the package directory `entry`, merged from every mapping.
+    fn package_directory_names(
+        &self,
+        source_map: &HostSourceMap,
+        entry: &str,
+    ) -> Result<BTreeSet<String>, Error> {
+        let mut names = BTreeSet::new();
+        for mapping in source_map.source_map.search_path.iter() {
+            let package_path = mapping.package_path.trim_matches('/');
+            if let Some(rest) = relative_path(entry, package_path) {
+                if !rest.is_empty() {
+                    names.insert(rest.split('/').next().unwrap_or_default().to_string());
+                }
+            }
+            let rest = match relative_path(package_path, entry) {
+                Some(rest) if !is_hidden(mapping, rest) => rest,
+                _ => continue,
+            };
+            let read_dir = match std::fs::read_dir(source_map.host_path(self, mapping, rest)) {
+                Ok(read_dir) => read_dir,
+                Err(err)
+                    if matches!(err.kind(), ErrorKind::NotFound | ErrorKind::NotADirectory) =>
+                {
+                    continue
+                }
+                Err(err) => return Err(err.into()),
+            };
+            for dir_entry in read_dir {
+                let name = dir_entry?.file_name().to_string_lossy().into_owned();
+                if !is_hidden(mapping, &join(rest, &name)) {
+                    names.insert(name);
+                }
+            }
+        }
+        Ok(names)
+    }
+}
+
+impl<'a> HostSourceMap<'a> {
+    /// Returns the path on the host of `rest` beneath the source path of `mapping`.
+    fn host_path(&self, files_list: &FilesList, mapping: &Mapping, rest: &str) -> PathBuf {
+        let source = if mapping.source_path.starts_with('/') {
+            files_list.resolve(&mapping.source_path)
+        } else {
+            self.pkgdef_directory.join(&mapping.source_path)
+        };
+        // Collecting the components drops the `.` of a source path such as `.`.
+        source.join(rest).components().collect()
+    }
+}
+
+/// Returns `path` relative to `directory`, or `None` if it is not `directory` or beneath it.
+/// Both are package paths without a leading `/`, and the root is empty.
+fn relative_path<'p>(directory: &str, path: &'p str) -> Option<&'p str> {
+    if directory.is_empty() {
+        return Some(path);
+    }
+    match path.strip_prefix(directory)? {
+        "" => Some(""),
+        rest => rest.strip_prefix('/'),
+    }
+}
+
+/// Returns `true` if `hidePaths` of `mapping` hides `rest`, a path relative to its source path.
+fn is_hidden(mapping: &Mapping, rest: &str) -> bool {
+    mapping.hide_paths.iter().any(|hide_path| {
+        let hide_path = hide_path.trim_matches('/');
+        !hide_path.is_empty() && relative_path(hide_path, rest).is_some()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::files_list::tests::test_tree::write_file;
+
+    #[test]
+    fn files_list_package_contents() {
+        use std::os::unix::fs::symlink;
+
+        let root = tempfile::tempdir().unwrap();
+        let pkgdef_directory = tempfile::tempdir().unwrap();
+        let files_list_file = pkgdef_directory.path().join("sandstorm-files.list");
+        for file in [
+            "etc/passwd",
+            "opt/app/.git/config",
+            "opt/app/manage.py",
+            "opt/app/static/site.css",
+            "usr/bin/python3.9",
+        ] {
+            write_file(root.path(), file, "");
+        }
+        symlink("python3.9", root.path().join("usr/bin/python3")).unwrap();
+        std::fs::write(pkgdef_directory.path().join("launcher.sh"), "").unwrap();
+        std::fs::write(
+            &files_list_file,
+            "etc/passwd\n\
+             missing/file\n\
+             opt/app/.sandstorm/launcher.sh\n\
+             usr/bin/python3\n\
+             usr/bin/python3.9\n",
+        )
+        .unwrap();
+        let pkgdef = Pkgdef::parse(
+            "const pkgdef :Spk.PackageDefinition = (\n\
+               sourceMap = (searchPath = [\n\
+                 (packagePath = \"opt/app/.sandstorm\", sourcePath = \".\",\n\
+                  hidePaths = [\"sandstorm-files.list\"]),\n\
+                 (sourcePath = \"/\", hidePaths = [\"etc/passwd\", \"opt/app/.git\"])\n\
+               ]),\n\
+               alwaysInclude = [\"opt/app\", \"../etc\"]\n\
+             );\n",
+        )
+        .unwrap();
+
+        let mut files_list = FilesList::with_root(&files_list_file, root.path());
+        files_list.load().unwrap();
+        let contents = files_list
+            .package_contents(&pkgdef, pkgdef_directory.path())
+            .unwrap();
+        assert_eq!(
+            vec![
+                (
+                    "/opt/app/.sandstorm/launcher.sh".to_string(),
+                    pkgdef_directory.path().join("launcher.sh")
+                ),
+                (
+                    "/opt/app/manage.py".to_string(),
+                    root.path().join("opt/app/manage.py")
+                ),
+                (
+                    "/opt/app/static/site.css".to_string(),
+                    root.path().join("opt/app/static/site.css")
+                ),
+                (
+                    "/usr/bin/python3.9".to_string(),
+                    root.path().join("usr/bin/python3.9")
+                ),
+            ],
+            contents.files.into_iter().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![
+                "/opt",
+                "/opt/app",
+                "/opt/app/.sandstorm",
+                "/opt/app/static",
+                "/usr",
+                "/usr/bin",
+            ],
+            contents.directories.into_iter().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            BTreeMap::from([("/usr/bin/python3".to_string(), "python3.9".to_string())]),
+            contents.symlinks
+        );
+        assert_eq!(
+            vec!["/../etc", "/etc/passwd", "/missing/file"],
+            contents.missing.into_iter().collect::<Vec<_>>()
+        );
+
+        files_list.set_source_map(pkgdef.source_map, pkgdef_directory.path());
+        assert!(files_list.is_reachable("usr/bin/python3.9").unwrap());
+        assert!(!files_list.is_reachable("..").unwrap());
+    }
+}
@@ -0,0 +1,127 @@
+//! Entries in a `FilesList` which no longer exist
+
+use std::collections::BTreeSet;
+
+use super::{EntryKind, FilesList};
+use crate::error::Error;
+
+impl FilesList {
+    /// Removes the entries which do not exist beneath the root from the files list file.
+    pub fn exclude_missing_entries(&mut self) -> Result<BTreeSet<String>, Error> {
+        self.load()?;
+        let removed_entries = self.prune()?;
+        self.save()?;
+        Ok(removed_entries)
+    }
+
+    /// Removes the entries which do not exist beneath the root from the files list without writing
+    /// the files list file.
+    ///
+    /// After an upgrade, entries such as `__init__.cpython-39.pyc` may no longer exist, and
+    /// `spk pack` fails on them.  Whether an entry exists is decided by `entry_kind`, which
+    /// resolves the symbolic links in its directories beneath the root but does not follow the
+    /// entry itself, so a listed dangling symbolic link is kept.  The comments and blank lines before a removed entry are kept with the entry
+    /// which follows it, and the headers and trailers of the files list are kept.  The removed
+    /// entries are returned with a leading `/`.
+    pub fn prune(&mut self) -> Result<BTreeSet<String>, Error> {
+        let mut missing: BTreeSet<String> = BTreeSet::new();
+        for entry in self.entries() {
+            if self.entry_kind(entry)? == EntryKind::Missing {
+                missing.insert(entry.to_string());
+            }
+        }
+        for entry in missing.iter() {
+            self.remove(entry);
+        }
+
+        Ok(missing
+            .into_iter()
+            .map(|entry| format!("/{}", entry))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::files_list::tests::test_tree::tree;
+
+    #[test]
+    fn files_list_prune() {
+        use std::os::unix::fs::symlink;
+
+        let (root, files_list_file) = tree(
+            &[
+                "opt/app/__pycache__/__init__.cpython-310.pyc",
+                "opt/app/__init__.py",
+            ],
+            "# Generated by spk dev\n\
+             \n\
+             # The application\n\
+             opt/app/__init__.py\n\
+             # Python 3.9 bytecode\n\
+             opt/app/__pycache__/__init__.cpython-39.pyc\n\
+             opt/app/__pycache__/__init__.cpython-310.pyc\n\
+             opt/app/broken\n\
+             opt/app/__init__.py/missing\n\
+             opt/old/app.py\n\
+             # The end\n",
+        );
+        symlink("missing", root.path().join("opt/app/broken")).unwrap();
+
+        let mut files_list = FilesList::with_root(&files_list_file, root.path());
+        let removed = files_list.exclude_missing_entries().unwrap();
+        assert_eq!(
+            vec![
+                "/opt/app/__init__.py/missing",
+                "/opt/app/__pycache__/__init__.cpython-39.pyc",
+                "/opt/old/app.py",
+            ],
+            removed.into_iter().collect::<Vec<_>>()
+        );
+        drop(files_list);
+        assert_eq!(
+            "# Generated by spk dev\n\
+             \n\
+             # The application\n\
+             opt/app/__init__.py\n\
+             opt/app/__pycache__/__init__.cpython-310.pyc\n\
+             # Python 3.9 bytecode\n\
+             opt/app/broken\n\
+             # The end\n",
+            std::fs::read_to_string(&files_list_file).unwrap()
+        );
+
+        let mut files_list = FilesList::with_root(&files_list_file, root.path());
+        files_list.load().unwrap();
+        assert!(files_list.prune().unwrap().is_empty());
+    }
+
+    #[test]
+    fn files_list_prune_resolves_absolute_symlinks_beneath_root() {
+        use std::os::unix::fs::symlink;
+
+        let (root, files_list_file) = tree(
+            &["opt/app/releases/2/app.py"],
+            "etc/host/passwd\n\
+             opt/app/current/app.py\n\
+             opt/app/current/gone.py\n",
+        );
+        symlink("/opt/app/releases/2", root.path().join("opt/app/current")).unwrap();
+        std::fs::create_dir(root.path().join("etc")).unwrap();
+        symlink("/etc", root.path().join("etc/host")).unwrap();
+
+        let mut files_list = FilesList::with_root(&files_list_file, root.path());
+        files_list.load().unwrap();
+        assert_eq!(
+            vec!["/etc/host/passwd", "/opt/app/current/gone.py"],
+            files_list.prune().unwrap().into_iter().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec!["opt/app/current/app.py"],
+            files_list.entries().collect::<Vec<_>>()
+        );
+    }
+}
@@ -0,0 +1,482 @@
+//! Python bytecode files for Python source files in a `FilesList`
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use super::bytecode::{cached_bytecode_name, PYCACHE_DIRECTORY, PY_EXTENSION};
+use super::FilesList;
+use crate::error::Error;
+
+/// The result of `FilesList::add_python_bytecode_files`.
+#[derive(Debug, Default, PartialEq)]
+pub struct PythonBytecodeFiles {
+    /// The bytecode files which were added, with a leading `/`.
+    pub added: BTreeSet<String>,
+    /// The listed Python source files which have no bytecode files, with a leading `/`.
+    pub without_bytecode: BTreeSet<String>,
+    /// The errors from compiling Python source files, by source file with a leading `/`.
+    pub compile_errors: BTreeMap<String, String>,
+}
+
+/// Prints the cache tag of the interpreter.
+const CACHE_TAG_SCRIPT: &str = "import sys; print(sys.implementation.cache_tag)";
+
+/// Compiles the Python source files read from standard input.
+///
+/// Each line of input is an optimization level, the path of a source file and the path to show in
+/// tracebacks, separated by tabs.  Each line of output is `ok` and the path of the bytecode file
+/// or `error`, the path to show in tracebacks and the error, separated by tabs.  The bytecode files
+/// keep the default invalidation mode, in which the interpreter checks them against the
+/// modification times of their source files, so that a source file changed after compiling is
+/// not shadowed by stale bytecode.
+const COMPILE_SCRIPT: &str = r#"
+import py_compile, sys
+for line in sys.stdin:
+    optimize, source, display = line.rstrip("\n").split("\t")
+    try:
+        cfile = py_compile.compile(
+            source, dfile=display, doraise=True, optimize=int(optimize)
+        )
+        print("ok\t" + cfile, flush=True)
+    except Exception as error:
+        message = " ".join(str(error).split())
+        print("error\t" + display + "\t" + message, flush=True)
+"#;
+
+/// Prints the paths of the bytecode files which `COMPILE_SCRIPT` would write for the Python
+/// source files read from standard input, without compiling them.
+///
+/// The input is the input of `COMPILE_SCRIPT` and each line of output is `ok` and the path of the
+/// bytecode file, separated by a tab.
+const CACHE_FILE_SCRIPT: &str = r#"
+import importlib.util, sys
+for line in sys.stdin:
+    optimize, source, display = line.rstrip("\n").split("\t")
+    optimization = int(optimize) if int(optimize) >= 1 else ""
+    cfile = importlib.util.cache_from_source(source, optimization=optimization)
+    print("ok\t" + cfile, flush=True)
+"#;
+
+impl FilesList {
+    /// Adds Python bytecode files to the files list file.
+    pub fn include_python_bytecode_files(
+        &mut self,
+        cache_tag: Option<&str>,
+    ) -> Result<PythonBytecodeFiles, Error> {
+        self.load()?;
+        let bytecode_files = self.add_python_bytecode_files(cache_tag)?;
+        self.save()?;
+        Ok(bytecode_files)
+    }
+
+    /// Adds Python bytecode files to the files list without writing the files list file.
+    ///
+    /// Sandstorm packages are read-only, so Python cannot write bytecode files when a grain
+    /// starts.  For each listed Python source file, such as `app/views.py`,
+    /// `add_python_bytecode_files` adds its bytecode files, such as
+    /// `app/__pycache__/views.cpython-39.pyc`, if they are present beneath the root and are not
+    /// listed.
+    ///
+    /// `cache_tag` is the cache tag of the interpreter, such as `cpython-39`.  If it is `None`, the
+    /// cache tags of the bytecode files already in the files list are used.  Bytecode files are
+    /// added at the optimization levels already in the files list, or without optimization if
+    /// there are none.
+    pub fn add_python_bytecode_files(
+        &mut self,
+        cache_tag: Option<&str>,
+    ) -> Result<PythonBytecodeFiles, Error> {
+        let cache_tags: Vec<String> = match cache_tag {
+            Some(cache_tag) => vec![cache_tag.to_string()],
+            None => self
+                .cached_bytecode()
+                .filter(|bytecode| match &self.implementation_tags {
+                    Some(implementation_tags) => bytecode.is_from(implementation_tags),
+                    None => true,
+                })
+                .map(|bytecode| bytecode.cache_tag.to_string())
+                .collect::<BTreeSet<String>>()
+                .into_iter()
+                .collect(),
+        };
+        if cache_tags.is_empty() {
+            return Err(Error::NoCacheTag(
+                self.filepath.to_string_lossy().to_string(),
+            ));
+        }
+        let mut optimization_levels: Vec<Option<String>> =
+            self.optimization_levels().into_keys().collect();
+        if optimization_levels.is_empty() {
+            optimization_levels.push(None);
+        }
+
+        let mut bytecode_files = PythonBytecodeFiles::default();
+        for source in self.entries() {
+            let (directory, module) = match source.strip_suffix(PY_EXTENSION) {
+                Some(without_extension) => match without_extension.rsplit_once('/') {
+                    Some((directory, module)) => (format!("{}/", directory), module),
+                    None => (String::new(), without_extension),
+                },
+                None => continue,
+            };
+            if module.is_empty() || directory.contains(PYCACHE_DIRECTORY) {
+                continue;
+            }
+
+            let mut has_bytecode = false;
+            for cache_tag in cache_tags.iter() {
+                for optimization_level in optimization_levels.iter() {
+                    let candidate = format!(
+                        "{}{}/{}",
+                        directory,
+                        PYCACHE_DIRECTORY,
+                        cached_bytecode_name(module, cache_tag, optimization_level.as_deref())
+                    );
+                    if self.contains(&candidate) {
+                        has_bytecode = true;
+                    } else if self.resolve(&candidate).is_file() {
+                        has_bytecode = true;
+                        bytecode_files.added.insert(format!("/{}", candidate));
+                    }
+                }
+            }
+            if !has_bytecode {
+                bytecode_files
+                    .without_bytecode
+                    .insert(format!("/{}", source));
+            }
+        }
+        self.insert_reachable_entries(&mut bytecode_files.added)?;
+
+        Ok(bytecode_files)
+    }
+
+    /// Compiles Python bytecode files and adds them to the files list without writing the files
+    /// list file.
+    ///
+    /// `interpreter` is the path of a Python 3 interpreter beneath the root, such as
+    /// `opt/app-venv/bin/python`, which must be able to run on this host.  First, existing
+    /// bytecode files with the interpreter's cache tag are added as described by
+    /// `add_python_bytecode_files`.  Then the interpreter compiles each listed Python source file
+    /// which has no bytecode file into its `__pycache__` directory and the new bytecode files are
+    /// added.  Source files which fail to compile are reported in `compile_errors` and remain in
+    /// `without_bytecode`.
+    pub fn compile_python_bytecode_files(
+        &mut self,
+        interpreter: &str,
+    ) -> Result<PythonBytecodeFiles, Error> {
+        self.python_bytecode_files_from(interpreter, true)
+    }
+
+    /// Adds the Python bytecode files which `compile_python_bytecode_files` would compile to the
+    /// files list without compiling them or writing the files list file.
+    ///
+    /// The interpreter only reports the paths of the bytecode files, so nothing is written beneath
+    /// the root.  As the bytecode files do not exist yet, they are added without checking them
+    /// against the source map, and source files which would fail to compile are not found.
+    pub fn add_python_bytecode_files_to_compile(
+        &mut self,
+        interpreter: &str,
+    ) -> Result<PythonBytecodeFiles, Error> {
+        self.python_bytecode_files_from(interpreter, false)
+    }
+
+    /// Adds existing bytecode files and the bytecode files of the listed Python source files
+    /// without bytecode, which the interpreter compiles if `compile` is `true` and otherwise only
+    /// reports.
+    fn python_bytecode_files_from(
+        &mut self,
+        interpreter: &str,
+        compile: bool,
+    ) -> Result<PythonBytecodeFiles, Error> {
+        let interpreter = self.resolve(interpreter);
+        let cache_tag = self.python_cache_tag(&interpreter)?;
+        let mut bytecode_files = self.add_python_bytecode_files(Some(&cache_tag))?;
+        if bytecode_files.without_bytecode.is_empty() {
+            return Ok(bytecode_files);
+        }
+
+        let mut optimization_levels: Vec<String> = self
+            .optimization_levels()
+            .into_keys()
+            .filter_map(|level| match level {
+                Some(level) if level.parse::<u8>().is_ok() => Some(level),
+                Some(_) => None,
+                None => Some("0".to_string()),
+            })
+            .collect();
+        if optimization_levels.is_empty() {
+            optimization_levels.push("0".to_string());
+        }
+        let mut input = String::new();
+        for source in bytecode_files.without_bytecode.iter() {
+            let source_path = self.resolve(source);
+            let source_path = match source_path.to_str() {
+                Some(source_path) => source_path,
+                None => return Err(Error::UnableToProcessNonUtf8Path(source.clone())),
+            };
+            for optimization_level in optimization_levels.iter() {
+                input.push_str(&format!(
+                    "{}\t{}\t{}\n",
+                    optimization_level, source_path, source
+                ));
+            }
+        }
+
+        let script = if compile {
+            COMPILE_SCRIPT
+        } else {
+            CACHE_FILE_SCRIPT
+        };
+        for line in self.run_python(&interpreter, script, input)? {
+            let mut fields = line.splitn(3, '\t');
+            match (fields.next(), fields.next(), fields.next()) {
+                (Some("ok"), Some(cfile), None) => {
+                    let entry = match Path::new(cfile).strip_prefix(&self.root) {
+                        Ok(entry) => entry.to_string_lossy().to_string(),
+                        Err(_) => {
+                            return Err(Error::PythonInterpreterFailed(
+                                interpreter.to_string_lossy().to_string(),
+                                format!("bytecode file outside of the root: {}", cfile),
+                            ))
+                        }
+                    };
+                    let inserted = if compile {
+                        self.insert_reachable(&entry)?
+                    } else {
+                        self.insert(&entry)
+                    };
+                    if inserted {
+                        for source in self.suggest_python_sources_for(&entry)? {
+                            bytecode_files.without_bytecode.remove(&source);
+                        }
+                        bytecode_files.added.insert(format!("/{}", entry));
+                    }
+                }
+                (Some("error"), Some(source), Some(message)) => {
+                    bytecode_files
+                        .compile_errors
+                        .insert(source.to_string(), message.to_string());
+                }
+                _ => {
+                    return Err(Error::PythonInterpreterFailed(
+                        interpreter.to_string_lossy().to_string(),
+                        format!("unexpected output: {}", line),
+                    ))
+                }
+            }
+        }
+
+        Ok(bytecode_files)
+    }
+
+    /// Returns the cache tag of `interpreter`, such as `cpython-39`.
+    fn python_cache_tag(&self, interpreter: &Path) -> Result<String, Error> {
+        match self
+            .run_python(interpreter, CACHE_TAG_SCRIPT, String::new())?
+            .into_iter()
+            .next()
+        {
+            Some(cache_tag) if !cache_tag.is_empty() && cache_tag != "None" => Ok(cache_tag),
+            _ => Err(Error::PythonInterpreterFailed(
+                interpreter.to_string_lossy().to_string(),
+                "no cache tag".to_string(),
+            )),
+        }
+    }
+
+    /// Runs `script` with `interpreter`, writing `input` to its standard input, and returns the
+    /// lines of its standard output.
+    fn run_python(
+        &self,
+        interpreter: &Path,
+        script: &str,
+        input: String,
+    ) -> Result<Vec<String>, Error> {
+        let interpreter_failed = |message: String| {
+            Error::PythonInterpreterFailed(interpreter.to_string_lossy().to_string(), message)
+        };
+
+        // -E and -s keep the environment and the user's site-packages out of the compilation.
+        let mut child = Command::new(interpreter)
+            .args(["-E", "-s", "-c", script])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|err| interpreter_failed(err.to_string()))?;
+        let mut stdin = child
+            .stdin
+            .take()
+            .expect("Unable to write to the interpreter");
+        // Write from another thread so that neither process waits for the other's pipe to drain.
+        let writer = std::thread::spawn(move || stdin.write_all(input.as_bytes()));
+        let stdout = child
+            .stdout
+            .take()
+            .expect("Unable to read from the interpreter");
+        let lines = BufReader::new(stdout)
+            .lines()
+            .collect::<Result<Vec<String>, _>>()?;
+        let status = child.wait()?;
+        match writer.join() {
+            Ok(result) => result?,
+            Err(_) => return Err(interpreter_failed("failed to write input".to_string())),
+        }
+        if !status.success() {
+            return Err(interpreter_failed(status.to_string()));
+        }
+        Ok(lines)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::files_list::tests::test_tree::{tree, tree_with_contents};
+
+    /// Returns the path of `python3` on the host, or `None` if it cannot be run.
+    fn python3() -> Option<String> {
+        match std::process::Command::new("python3")
+            .args(["-c", "import sys; print(sys.executable)"])
+            .output()
+        {
+            Ok(output) if output.status.success() => {
+                Some(String::from_utf8(output.stdout).unwrap().trim().to_string())
+            }
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn files_list_add_python_bytecode_files() {
+        let (root, files_list_file) = tree(
+            &[
+                "opt/app/manage.py",
+                "opt/app/models.py",
+                "opt/app/views.py",
+                "opt/app/__pycache__/models.cpython-39.pyc",
+                "opt/app/__pycache__/models.pypy39.pyc",
+                "opt/app/__pycache__/views.cpython-39.pyc",
+            ],
+            "opt/app/__pycache__/views.cpython-39.pyc\n\
+             opt/app/manage.py\n\
+             opt/app/models.py\n\
+             opt/app/views.py\n",
+        );
+
+        let mut files_list = FilesList::with_root(&files_list_file, root.path());
+        files_list.load().unwrap();
+        let bytecode_files = files_list.add_python_bytecode_files(None).unwrap();
+        assert_eq!(
+            vec!["/opt/app/__pycache__/models.cpython-39.pyc"],
+            bytecode_files.added.into_iter().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec!["/opt/app/manage.py"],
+            bytecode_files
+                .without_bytecode
+                .into_iter()
+                .collect::<Vec<_>>()
+        );
+        assert!(files_list.contains("opt/app/__pycache__/models.cpython-39.pyc"));
+
+        let bytecode_files = files_list
+            .add_python_bytecode_files(Some("pypy39"))
+            .unwrap();
+        assert_eq!(
+            vec!["/opt/app/__pycache__/models.pypy39.pyc"],
+            bytecode_files.added.into_iter().collect::<Vec<_>>()
+        );
+        assert_eq!(2, bytecode_files.without_bytecode.len());
+
+        files_list.remove("opt/app/__pycache__/models.cpython-39.pyc");
+        files_list.remove("opt/app/__pycache__/models.pypy39.pyc");
+        files_list.remove("opt/app/__pycache__/views.cpython-39.pyc");
+        assert!(matches!(
+            files_list.add_python_bytecode_files(None),
+            Err(Error::NoCacheTag(_))
+        ));
+    }
+
+    #[test]
+    fn files_list_compile_python_bytecode_files() {
+        let python = match python3() {
+            Some(python) => python,
+            None => {
+                eprintln!("Skipping files_list_compile_python_bytecode_files without python3");
+                return;
+            }
+        };
+        let (root, files_list_file) = tree_with_contents(
+            &[
+                ("opt/app/broken.py", "def index(:\n"),
+                ("opt/app/views.py", "def index():\n    return 1\n"),
+            ],
+            "opt/app/broken.py\n\
+             opt/app/views.py\n",
+        );
+        std::fs::create_dir_all(root.path().join("usr/bin")).unwrap();
+        std::os::unix::fs::symlink(&python, root.path().join("usr/bin/python3")).unwrap();
+
+        let mut files_list = FilesList::with_root(&files_list_file, root.path());
+        files_list.load().unwrap();
+        let bytecode_files = files_list
+            .compile_python_bytecode_files("usr/bin/python3")
+            .unwrap();
+
+        assert_eq!(1, bytecode_files.added.len());
+        let added = bytecode_files.added.iter().next().unwrap();
+        assert!(added.starts_with("/opt/app/__pycache__/views."));
+        assert!(files_list.contains(added));
+        assert!(files_list.resolve(added).is_file());
+        assert_eq!(
+            vec!["/opt/app/broken.py"],
+            bytecode_files
+                .without_bytecode
+                .into_iter()
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec!["/opt/app/broken.py"],
+            bytecode_files
+                .compile_errors
+                .into_keys()
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn files_list_add_python_bytecode_files_to_compile() {
+        let python = match python3() {
+            Some(python) => python,
+            None => {
+                eprintln!(
+                    "Skipping files_list_add_python_bytecode_files_to_compile without python3"
+                );
+                return;
+            }
+        };
+        let (root, files_list_file) = tree_with_contents(
+            &[("opt/app/views.py", "def index():\n    return 1\n")],
+            "opt/app/views.py\n",
+        );
+        std::fs::create_dir_all(root.path().join("usr/bin")).unwrap();
+        std::os::unix::fs::symlink(&python, root.path().join("usr/bin/python3")).unwrap();
+
+        let mut files_list = FilesList::with_root(&files_list_file, root.path());
+        files_list.load().unwrap();
+        let bytecode_files = files_list
+            .add_python_bytecode_files_to_compile("usr/bin/python3")
+            .unwrap();
+
+        assert_eq!(1, bytecode_files.added.len());
+        let added = bytecode_files.added.iter().next().unwrap();
+        assert!(added.starts_with("/opt/app/__pycache__/views."));
+        assert!(files_list.contains(added));
+        assert!(!root.path().join("opt/app/__pycache__").exists());
+        assert!(bytecode_files.without_bytecode.is_empty());
+    }
+}
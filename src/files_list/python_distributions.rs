@@ -0,0 +1,481 @@
+//! Installed Python distributions, such as `Django-4.1.dist-info`, for the files in a `FilesList`
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::ErrorKind;
+
+use super::bytecode::{CachedBytecode, PYCACHE_DIRECTORY};
+use super::FilesList;
+use crate::error::Error;
+use crate::glob;
+
+const DIST_INFO_EXTENSION: &str = ".dist-info";
+const EGG_INFO_EXTENSION: &str = ".egg-info";
+const INSTALLED_FILES_FILE: &str = "installed-files.txt";
+const RECORD_FILE: &str = "RECORD";
+pub(crate) const SITE_PACKAGES_DIRECTORIES: [&str; 2] = ["site-packages", "dist-packages"];
+const TOP_LEVEL_FILE: &str = "top_level.txt";
+
+/// A Python distribution installed in a `site-packages` directory.
+pub(crate) struct Distribution {
+    /// The entry of the `site-packages` directory in which the distribution is installed.
+    pub site_packages: String,
+    /// The entries of the distribution's metadata, such as the files in
+    /// `site-packages/Django-4.1.dist-info`.
+    pub metadata_files: BTreeSet<String>,
+    /// The entries of the files installed by the distribution, according to its `RECORD` or
+    /// `installed-files.txt`.
+    pub files: BTreeSet<String>,
+    /// The top-level packages and modules of the distribution, such as `django`.
+    pub top_level: BTreeSet<String>,
+}
+
+impl Distribution {
+    /// Returns `true` if the distribution owns `entry`.
+    ///
+    /// The distribution owns its installed files and metadata files, the directories which hold
+    /// them and the cached bytecode of its installed files.  A distribution without a list of its
+    /// installed files owns the entries beneath its top-level packages and modules.
+    fn owns(&self, entry: &str) -> bool {
+        if self.files.contains(entry) || self.metadata_files.contains(entry) {
+            return true;
+        }
+        let directory = format!("{}/", entry);
+        if self
+            .files
+            .range(directory.clone()..)
+            .next()
+            .is_some_and(|file| file.starts_with(&directory))
+        {
+            return true;
+        }
+        if let Some((parent, name)) = entry.rsplit_once('/') {
+            if let Some(directory) = parent.strip_suffix(PYCACHE_DIRECTORY) {
+                if let Some(bytecode) = CachedBytecode::parse(name) {
+                    let source = format!("{}{}.py", directory, bytecode.module);
+                    if self.files.contains(&source) {
+                        return true;
+                    }
+                }
+            }
+        }
+        self.files.is_empty()
+            && entry
+                .strip_prefix(&self.site_packages)
+                .and_then(|installed| installed.strip_prefix('/'))
+                .and_then(FilesList::top_level_name)
+                .is_some_and(|name| self.top_level.contains(name))
+    }
+}
+
+impl FilesList {
+    /// Adds the metadata of installed Python distributions to the files list file.
+    pub fn include_python_distribution_metadata(&mut self) -> Result<BTreeSet<String>, Error> {
+        self.load()?;
+        let added_metadata = self.add_python_distribution_metadata()?;
+        self.save()?;
+        Ok(added_metadata)
+    }
+
+    /// Adds the metadata of installed Python distributions to the files list without writing the
+    /// files list file.
+    ///
+    /// `importlib.metadata` and entry points need the `.dist-info` or `.egg-info` metadata of a
+    /// distribution, but Python may never open it during a dev session.  For each distribution
+    /// in a `site-packages` directory which owns a listed entry, according to its `RECORD`,
+    /// `add_python_distribution_metadata` adds the files of its metadata directory.  Only
+    /// distributions without a `RECORD` or `installed-files.txt` are matched by their
+    /// `top_level.txt`, as distributions which share a namespace package, such as `google`, have
+    /// the same top-level names.  The added files are returned with a leading `/`.
+    pub fn add_python_distribution_metadata(&mut self) -> Result<BTreeSet<String>, Error> {
+        let mut added_metadata: BTreeSet<String> = BTreeSet::new();
+
+        for distribution in self.referenced_python_distributions()? {
+            for file in distribution.metadata_files.iter() {
+                if !self.contains(file) {
+                    added_metadata.insert(format!("/{}", file));
+                }
+            }
+        }
+        self.insert_reachable_entries(&mut added_metadata)?;
+
+        Ok(added_metadata)
+    }
+
+    /// Adds every file of the installed Python distributions which own listed files to the files
+    /// list file.
+    pub fn include_python_distribution_files<S: AsRef<str>>(
+        &mut self,
+        excludes: &[S],
+    ) -> Result<BTreeSet<String>, Error> {
+        self.load()?;
+        let added_files = self.add_python_distribution_files(excludes)?;
+        self.save()?;
+        Ok(added_files)
+    }
+
+    /// Adds every file of the installed Python distributions which own listed files to the files
+    /// list without writing the files list file.
+    ///
+    /// A dev session rarely imports every module of a distribution or opens all of its templates
+    /// and translations.  For each distribution which owns a listed entry, as described by
+    /// `add_python_distribution_metadata`, `add_python_distribution_files` adds each file in its
+    /// `RECORD` or `installed-files.txt` which is present beneath the root.
+    ///
+    /// Files which match one of the glob patterns `excludes`, such as `**/tests/**` or `*.pyi`, are
+    /// not added.  Patterns are matched against paths relative to the `site-packages` directory, as
+    /// described by `glob::matches`.  The added files are returned with a leading `/`.
+    pub fn add_python_distribution_files<S: AsRef<str>>(
+        &mut self,
+        excludes: &[S],
+    ) -> Result<BTreeSet<String>, Error> {
+        let mut added_files: BTreeSet<String> = BTreeSet::new();
+
+        for distribution in self.referenced_python_distributions()? {
+            let prefix = format!("{}/", distribution.site_packages);
+            for file in distribution.files.iter() {
+                let installed = file.strip_prefix(&prefix).unwrap_or(file);
+                if self.contains(file)
+                    || excludes
+                        .iter()
+                        .any(|exclude| glob::matches(exclude.as_ref(), installed))
+                {
+                    continue;
+                }
+                if self.resolve(file).symlink_metadata().is_ok() {
+                    added_files.insert(format!("/{}", file));
+                }
+            }
+        }
+        self.insert_reachable_entries(&mut added_files)?;
+
+        Ok(added_files)
+    }
+
+    /// Returns the installed Python distributions which own a listed entry.
+    pub(crate) fn referenced_python_distributions(&self) -> Result<Vec<Distribution>, Error> {
+        let mut referenced: BTreeMap<String, Vec<&str>> = BTreeMap::new();
+        for entry in self.entries() {
+            if let Some((site_packages, installed)) = FilesList::split_site_packages(entry) {
+                if !installed.is_empty() {
+                    referenced.entry(site_packages).or_default().push(entry);
+                }
+            }
+        }
+
+        let mut distributions = Vec::new();
+        for (site_packages, entries) in referenced.iter() {
+            for distribution in self.python_distributions_in(site_packages)? {
+                if entries.iter().any(|entry| distribution.owns(entry)) {
+                    distributions.push(distribution);
+                }
+            }
+        }
+        Ok(distributions)
+    }
+
+    /// Returns the Python distributions installed in the directory `site_packages`.
+    fn python_distributions_in(&self, site_packages: &str) -> Result<Vec<Distribution>, Error> {
+        let metadata_names: Vec<String> = self
+            .directory_names(site_packages)?
+            .into_iter()
+            .filter(|name| {
+                name.ends_with(DIST_INFO_EXTENSION) || name.ends_with(EGG_INFO_EXTENSION)
+            })
+            .collect();
+
+        let mut distributions = Vec::new();
+        for name in metadata_names {
+            let metadata = format!("{}/{}", site_packages, name);
+            if !self.resolve(&metadata).is_dir() {
+                // distutils writes the metadata of an egg to a single file.
+                distributions.push(Distribution {
+                    site_packages: site_packages.to_string(),
+                    metadata_files: BTreeSet::from([metadata]),
+                    files: BTreeSet::new(),
+                    top_level: BTreeSet::new(),
+                });
+                continue;
+            }
+
+            let files: BTreeSet<String> = if name.ends_with(DIST_INFO_EXTENSION) {
+                self.read_metadata_lines(&metadata, RECORD_FILE)?
+                    .iter()
+                    .filter_map(|line| FilesList::record_path(line))
+                    .filter_map(|path| {
+                        FilesList::normalize_path(&format!("{}/{}", site_packages, path))
+                    })
+                    .collect()
+            } else {
+                self.read_metadata_lines(&metadata, INSTALLED_FILES_FILE)?
+                    .iter()
+                    .filter_map(|path| FilesList::normalize_path(&format!("{}/{}", metadata, path)))
+                    .collect()
+            };
+            let top_level: BTreeSet<String> = self
+                .read_metadata_lines(&metadata, TOP_LEVEL_FILE)?
+                .iter()
+                .filter_map(|line| line.split('/').next())
+                .map(|name| name.to_string())
+                .collect();
+
+            distributions.push(Distribution {
+                site_packages: site_packages.to_string(),
+                metadata_files: self.files_beneath(&metadata)?,
+                files,
+                top_level,
+            });
+        }
+        Ok(distributions)
+    }
+
+    /// Returns the non-empty lines of the file `name` in the metadata directory `metadata`, or
+    /// nothing if the file does not exist.
+    fn read_metadata_lines(&self, metadata: &str, name: &str) -> Result<Vec<String>, Error> {
+        match std::fs::read_to_string(self.resolve(&format!("{}/{}", metadata, name))) {
+            Ok(contents) => Ok(contents
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty())
+                .map(String::from)
+                .collect()),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(Vec::new()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Splits `entry` into its `site-packages` directory and the path beneath it.
+    pub(crate) fn split_site_packages(entry: &str) -> Option<(String, String)> {
+        let components: Vec<&str> = entry.split('/').collect();
+        let index = components
+            .iter()
+            .position(|component| SITE_PACKAGES_DIRECTORIES.contains(component))?;
+        Some((
+            components[..=index].join("/"),
+            components[index + 1..].join("/"),
+        ))
+    }
+
+    /// Returns the name of the top-level package or module of `installed`, a path beneath a
+    /// `site-packages` directory.
+    ///
+    /// The name of `django/shortcuts.py` is `django`, the name of `six.py` is `six` and the name of
+    /// `__pycache__/six.cpython-39.pyc` is `six`.
+    fn top_level_name(installed: &str) -> Option<&str> {
+        let mut components = installed.split('/');
+        let first = components.next()?;
+        let name = if first == PYCACHE_DIRECTORY {
+            CachedBytecode::parse(components.next()?)?.module
+        } else {
+            first.split('.').next()?
+        };
+        if name.is_empty() || name.ends_with(DIST_INFO_EXTENSION) {
+            None
+        } else {
+            Some(name)
+        }
+    }
+
+    /// Returns the path in a line of a `RECORD` file, which is a CSV file of paths, hashes and
+    /// sizes.
+    fn record_path(line: &str) -> Option<String> {
+        match line.strip_prefix('"') {
+            Some(quoted) => {
+                let mut path = String::new();
+                let mut characters = quoted.chars().peekable();
+                while let Some(character) = characters.next() {
+                    if character == '"' {
+                        if characters.peek() == Some(&'"') {
+                            characters.next();
+                        } else {
+                            return Some(path);
+                        }
+                    }
+                    path.push(character);
+                }
+                None
+            }
+            None => line.split(',').next().map(String::from),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::files_list::tests::test_tree::tree_with_contents;
+
+    #[test]
+    fn files_list_add_python_distribution_metadata() {
+        let (root, files_list_file) = tree_with_contents(
+            &[
+                ("opt/app-venv/lib/python3.9/site-packages/django/__init__.py", ""),
+                ("opt/app-venv/lib/python3.9/site-packages/django/shortcuts.py", ""),
+                ("opt/app-venv/lib/python3.9/site-packages/Django-4.1.dist-info/METADATA", ""),
+                (
+                    "opt/app-venv/lib/python3.9/site-packages/Django-4.1.dist-info/entry_points.txt",
+                    "",
+                ),
+                (
+                    "opt/app-venv/lib/python3.9/site-packages/Django-4.1.dist-info/top_level.txt",
+                    "django\n",
+                ),
+                (
+                    "opt/app-venv/lib/python3.9/site-packages/Django-4.1.dist-info/RECORD",
+                    "django/__init__.py,sha256=abc,799\n\
+                     django/shortcuts.py,sha256=def,4896\n\
+                     Django-4.1.dist-info/RECORD,,\n",
+                ),
+                ("opt/app-venv/lib/python3.9/site-packages/six.py", ""),
+                ("opt/app-venv/lib/python3.9/site-packages/six-1.16.0.dist-info/METADATA", ""),
+                (
+                    "opt/app-venv/lib/python3.9/site-packages/six-1.16.0.dist-info/RECORD",
+                    "six.py,sha256=ghi,34549\n\
+                     __pycache__/six.cpython-39.pyc,,\n\
+                     \"six-1.16.0.dist-info/RECORD\",,\n",
+                ),
+                ("opt/app-venv/lib/python3.9/site-packages/requests/__init__.py", ""),
+                (
+                    "opt/app-venv/lib/python3.9/site-packages/requests-2.28.1.dist-info/METADATA",
+                    "",
+                ),
+                (
+                    "opt/app-venv/lib/python3.9/site-packages/requests-2.28.1.dist-info/RECORD",
+                    "requests/__init__.py,,\n",
+                ),
+            ],
+            "opt/app-venv/lib/python3.9/site-packages/__pycache__/six.cpython-39.pyc\n\
+             opt/app-venv/lib/python3.9/site-packages/django/shortcuts.py\n",
+        );
+
+        let mut files_list = FilesList::with_root(&files_list_file, root.path());
+        files_list.load().unwrap();
+        let added = files_list.add_python_distribution_metadata().unwrap();
+        assert_eq!(
+            vec![
+                "/opt/app-venv/lib/python3.9/site-packages/Django-4.1.dist-info/METADATA",
+                "/opt/app-venv/lib/python3.9/site-packages/Django-4.1.dist-info/RECORD",
+                "/opt/app-venv/lib/python3.9/site-packages/Django-4.1.dist-info/entry_points.txt",
+                "/opt/app-venv/lib/python3.9/site-packages/Django-4.1.dist-info/top_level.txt",
+                "/opt/app-venv/lib/python3.9/site-packages/six-1.16.0.dist-info/METADATA",
+                "/opt/app-venv/lib/python3.9/site-packages/six-1.16.0.dist-info/RECORD",
+            ],
+            added.into_iter().collect::<Vec<_>>()
+        );
+        assert!(files_list
+            .add_python_distribution_metadata()
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn files_list_add_python_distribution_metadata_of_namespace_packages() {
+        let (root, files_list_file) = tree_with_contents(
+            &[
+                ("opt/app-venv/lib/python3.9/site-packages/google/protobuf/__init__.py", ""),
+                ("opt/app-venv/lib/python3.9/site-packages/google/auth/__init__.py", ""),
+                (
+                    "opt/app-venv/lib/python3.9/site-packages/protobuf-4.21.12.dist-info/METADATA",
+                    "",
+                ),
+                (
+                    "opt/app-venv/lib/python3.9/site-packages/protobuf-4.21.12.dist-info/top_level.txt",
+                    "google\n",
+                ),
+                (
+                    "opt/app-venv/lib/python3.9/site-packages/protobuf-4.21.12.dist-info/RECORD",
+                    "google/protobuf/__init__.py,sha256=abc,346\n",
+                ),
+                (
+                    "opt/app-venv/lib/python3.9/site-packages/google_auth-2.15.0.dist-info/METADATA",
+                    "",
+                ),
+                (
+                    "opt/app-venv/lib/python3.9/site-packages/google_auth-2.15.0.dist-info/top_level.txt",
+                    "google\n",
+                ),
+                (
+                    "opt/app-venv/lib/python3.9/site-packages/google_auth-2.15.0.dist-info/RECORD",
+                    "google/auth/__init__.py,sha256=def,1082\n",
+                ),
+            ],
+            "opt/app-venv/lib/python3.9/site-packages/google/protobuf/__pycache__/__init__.cpython-39.pyc\n",
+        );
+
+        let mut files_list = FilesList::with_root(&files_list_file, root.path());
+        files_list.load().unwrap();
+        let added = files_list.add_python_distribution_metadata().unwrap();
+        assert_eq!(
+            vec![
+                "/opt/app-venv/lib/python3.9/site-packages/protobuf-4.21.12.dist-info/METADATA",
+                "/opt/app-venv/lib/python3.9/site-packages/protobuf-4.21.12.dist-info/RECORD",
+                "/opt/app-venv/lib/python3.9/site-packages/protobuf-4.21.12.dist-info/top_level.txt",
+            ],
+            added.into_iter().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn files_list_add_python_distribution_files() {
+        let (root, files_list_file) = tree_with_contents(
+            &[
+                ("opt/app-venv/bin/django-admin", ""),
+                ("opt/app-venv/lib/python3.9/site-packages/django/__init__.py", ""),
+                ("opt/app-venv/lib/python3.9/site-packages/django/__init__.pyi", ""),
+                (
+                    "opt/app-venv/lib/python3.9/site-packages/django/conf/locale/de/LC_MESSAGES/django.mo",
+                    "",
+                ),
+                (
+                    "opt/app-venv/lib/python3.9/site-packages/django/tests/test_views.py",
+                    "",
+                ),
+                ("opt/app-venv/lib/python3.9/site-packages/Django-4.1.dist-info/METADATA", ""),
+                (
+                    "opt/app-venv/lib/python3.9/site-packages/Django-4.1.dist-info/RECORD",
+                    "../../../bin/django-admin,sha256=abc,296\n\
+                     django/__init__.py,sha256=def,799\n\
+                     django/__init__.pyi,sha256=ghi,120\n\
+                     django/__pycache__/__init__.cpython-39.pyc,,\n\
+                     django/conf/locale/de/LC_MESSAGES/django.mo,sha256=jkl,28092\n\
+                     django/tests/test_views.py,sha256=mno,30\n\
+                     Django-4.1.dist-info/METADATA,sha256=pqr,4094\n\
+                     Django-4.1.dist-info/RECORD,,\n",
+                ),
+                ("opt/app-venv/lib/python3.9/site-packages/requests/__init__.py", ""),
+                (
+                    "opt/app-venv/lib/python3.9/site-packages/requests-2.28.1.dist-info/RECORD",
+                    "",
+                ),
+            ],
+            "opt/app-venv/lib/python3.9/site-packages/django/__init__.py\n",
+        );
+
+        let mut files_list = FilesList::with_root(&files_list_file, root.path());
+        files_list.load().unwrap();
+        let added = files_list
+            .add_python_distribution_files(&["**/tests/**", "*.pyi"])
+            .unwrap();
+        assert_eq!(
+            vec![
+                "/opt/app-venv/bin/django-admin",
+                "/opt/app-venv/lib/python3.9/site-packages/Django-4.1.dist-info/METADATA",
+                "/opt/app-venv/lib/python3.9/site-packages/Django-4.1.dist-info/RECORD",
+                "/opt/app-venv/lib/python3.9/site-packages/django/conf/locale/de/LC_MESSAGES/django.mo",
+            ],
+            added.into_iter().collect::<Vec<_>>()
+        );
+
+        let added = files_list
+            .add_python_distribution_files::<&str>(&[])
+            .unwrap();
+        assert_eq!(
+            vec![
+                "/opt/app-venv/lib/python3.9/site-packages/django/__init__.pyi",
+                "/opt/app-venv/lib/python3.9/site-packages/django/tests/test_views.py",
+            ],
+            added.into_iter().collect::<Vec<_>>()
+        );
+    }
+}
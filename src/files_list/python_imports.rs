@@ -0,0 +1,513 @@
+//! Python modules imported by the Python source files in a `FilesList`
+
+use std::collections::{BTreeSet, VecDeque};
+
+use super::bytecode::{CachedBytecode, PYCACHE_DIRECTORY, PY_EXTENSION};
+use super::python_distributions::SITE_PACKAGES_DIRECTORIES;
+use super::{join, parent_of, FilesList};
+use crate::error::Error;
+
+const EXTENSION_MODULE_EXTENSION: &str = ".so";
+const INIT_SOURCE: &str = "__init__.py";
+const LIB_DYNLOAD_DIRECTORY: &str = "lib-dynload";
+
+/// An `import` statement, or one module of an `import` statement which imports several.
+#[derive(Debug, PartialEq)]
+struct Import {
+    /// The number of leading `.` of a relative import, or 0.
+    level: usize,
+    /// The imported module, such as `django.db`, which is empty for `from . import views`.
+    module: String,
+    /// The names imported from the module by `from ... import`, which may be submodules.
+    names: Vec<String>,
+}
+
+/// A module found by `FilesList::resolve_python_module`.
+struct ResolvedModule {
+    /// The entries of the `__init__.py` of each package and of the module itself.
+    files: Vec<String>,
+    /// The directory of the module if it is a package.
+    package: Option<String>,
+}
+
+impl FilesList {
+    /// Adds the Python modules imported by listed Python source files to the files list file.
+    pub fn include_python_imported_modules(&mut self) -> Result<BTreeSet<String>, Error> {
+        self.load()?;
+        let added_modules = self.add_python_imported_modules()?;
+        self.save()?;
+        Ok(added_modules)
+    }
+
+    /// Adds the Python modules imported by listed Python source files to the files list without
+    /// writing the files list file.
+    ///
+    /// A dev session only lists the modules which were imported on the code paths it exercised.
+    /// `add_python_imported_modules` reads the `import` and `from ... import` statements of each
+    /// listed Python source file and resolves them against the `site-packages` and standard
+    /// library directories of the listed files, and against the directory which contains the top
+    /// package of the importing file.  The source files, or extension modules, of the resolved
+    /// packages and modules are added with their existing bytecode files, and the added source
+    /// files are read in turn.  Imports which cannot be resolved, such as those of built-in
+    /// modules, are ignored.  The added files are returned with a leading `/`.
+    pub fn add_python_imported_modules(&mut self) -> Result<BTreeSet<String>, Error> {
+        let search_path = self.python_search_path();
+        let mut added_modules: BTreeSet<String> = BTreeSet::new();
+        let mut sources: VecDeque<String> = self
+            .entries()
+            .filter(|entry| {
+                entry.ends_with(PY_EXTENSION)
+                    && !entry
+                        .split('/')
+                        .any(|component| component == PYCACHE_DIRECTORY)
+            })
+            .map(String::from)
+            .collect();
+        let mut read: BTreeSet<String> = sources.iter().cloned().collect();
+
+        while let Some(source) = sources.pop_front() {
+            let contents = match std::fs::read(self.resolve(&source)) {
+                Ok(contents) => contents,
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(err) => return Err(err.into()),
+            };
+            let package = parent_of(&source);
+            let mut modules: BTreeSet<String> = BTreeSet::new();
+            for import in python_imports(&String::from_utf8_lossy(&contents)) {
+                let base_directories: Vec<String> = if import.level > 0 {
+                    let mut directory = Some(package);
+                    for _ in 1..import.level {
+                        directory = directory.and_then(|directory| {
+                            if directory.is_empty() {
+                                None
+                            } else {
+                                Some(parent_of(directory))
+                            }
+                        });
+                    }
+                    directory.into_iter().map(String::from).collect()
+                } else {
+                    let mut directories = vec![self.python_script_directory(package)];
+                    directories.extend(search_path.iter().cloned());
+                    directories
+                };
+                let parts: Vec<&str> = import
+                    .module
+                    .split('.')
+                    .filter(|part| !part.is_empty())
+                    .collect();
+                for directory in base_directories.iter() {
+                    if let Some(module) = self.resolve_python_module(directory, &parts)? {
+                        modules.extend(module.files);
+                        if let Some(package) = module.package {
+                            for name in import.names.iter() {
+                                if let Some(submodule) =
+                                    self.resolve_python_module(&package, &[name])?
+                                {
+                                    modules.extend(submodule.files);
+                                }
+                            }
+                        }
+                        break;
+                    }
+                }
+            }
+
+            for module in modules {
+                for file in self.python_module_files(&module)? {
+                    if self.insert_reachable(&file)? {
+                        added_modules.insert(format!("/{}", file));
+                    }
+                }
+                if module.ends_with(PY_EXTENSION) && read.insert(module.clone()) {
+                    sources.push_back(module);
+                }
+            }
+        }
+
+        Ok(added_modules)
+    }
+
+    /// Returns the `site-packages` and standard library directories of the listed entries.
+    fn python_search_path(&self) -> Vec<String> {
+        let mut search_path: BTreeSet<String> = BTreeSet::new();
+        for entry in self.entries() {
+            let components: Vec<&str> = entry.split('/').collect();
+            for (index, component) in components.iter().enumerate() {
+                if SITE_PACKAGES_DIRECTORIES.contains(component) {
+                    search_path.insert(components[..=index].join("/"));
+                } else if index > 0
+                    && components[index - 1].starts_with("lib")
+                    && component.starts_with("python")
+                    && component[6..].starts_with(|c: char| c.is_ascii_digit())
+                    && index + 1 < components.len()
+                {
+                    let stdlib = components[..=index].join("/");
+                    search_path.insert(format!("{}/{}", stdlib, LIB_DYNLOAD_DIRECTORY));
+                    search_path.insert(stdlib);
+                }
+            }
+        }
+        search_path.into_iter().collect()
+    }
+
+    /// Returns the directory from which the top package containing the directory `package` is
+    /// imported, which is `package` itself if it is not a package.
+    fn python_script_directory(&self, mut package: &str) -> String {
+        while !package.is_empty() && self.resolve(&join(package, INIT_SOURCE)).is_file() {
+            package = parent_of(package);
+        }
+        package.to_string()
+    }
+
+    /// Resolves the module `parts`, such as `["django", "db"]`, beneath `directory`.
+    ///
+    /// Returns `None` if the module is not found.
+    fn resolve_python_module<S: AsRef<str>>(
+        &self,
+        directory: &str,
+        parts: &[S],
+    ) -> Result<Option<ResolvedModule>, Error> {
+        if !self.resolve(directory).is_dir() {
+            return Ok(None);
+        }
+        let mut found = Vec::new();
+        let mut current = directory.to_string();
+        for (index, part) in parts.iter().enumerate() {
+            let part = part.as_ref();
+            let package = join(&current, part);
+            let init = join(&package, INIT_SOURCE);
+            if self.resolve(&init).is_file() {
+                found.push(init);
+                current = package;
+                continue;
+            }
+            if index + 1 == parts.len() {
+                let source = format!("{}{}", package, PY_EXTENSION);
+                if self.resolve(&source).is_file() {
+                    found.push(source);
+                    return Ok(Some(ResolvedModule {
+                        files: found,
+                        package: None,
+                    }));
+                }
+                let prefix = format!("{}.", part);
+                if let Some(name) = self.directory_names(&current)?.into_iter().find(|name| {
+                    name.starts_with(&prefix) && name.ends_with(EXTENSION_MODULE_EXTENSION)
+                }) {
+                    found.push(join(&current, &name));
+                    return Ok(Some(ResolvedModule {
+                        files: found,
+                        package: None,
+                    }));
+                }
+            }
+            if self.resolve(&package).is_dir() {
+                // A namespace package has no `__init__.py`.
+                current = package;
+                continue;
+            }
+            return Ok(None);
+        }
+        Ok(Some(ResolvedModule {
+            files: found,
+            package: Some(current),
+        }))
+    }
+
+    /// Returns the entry of the module `module` and the entries of its bytecode files which are
+    /// present beneath the root.
+    fn python_module_files(&self, module: &str) -> Result<Vec<String>, Error> {
+        let mut files = vec![module.to_string()];
+        if let Some(without_extension) = module.strip_suffix(PY_EXTENSION) {
+            let (directory, name) = without_extension
+                .rsplit_once('/')
+                .unwrap_or(("", without_extension));
+            let pycache = join(directory, PYCACHE_DIRECTORY);
+            for bytecode_name in self.directory_names(&pycache)? {
+                let is_bytecode = match CachedBytecode::parse(&bytecode_name) {
+                    Some(bytecode) => {
+                        bytecode.module == name
+                            && match &self.implementation_tags {
+                                Some(implementation_tags) => bytecode.is_from(implementation_tags),
+                                None => true,
+                            }
+                    }
+                    None => false,
+                };
+                if is_bytecode {
+                    files.push(join(&pycache, &bytecode_name));
+                }
+            }
+        }
+        Ok(files)
+    }
+}
+
+/// Returns the imports of the Python source `source`.
+fn python_imports(source: &str) -> Vec<Import> {
+    let mut imports = Vec::new();
+    for statement in python_statements(source) {
+        let mut tokens = statement.split_whitespace();
+        match tokens.next() {
+            Some("import") => {
+                for module in tokens.collect::<Vec<_>>().join(" ").split(',') {
+                    if let Some(module) = module.split_whitespace().next() {
+                        if is_dotted_name(module) {
+                            imports.push(Import {
+                                level: 0,
+                                module: module.to_string(),
+                                names: Vec::new(),
+                            });
+                        }
+                    }
+                }
+            }
+            Some("from") => {
+                let module = match tokens.next() {
+                    Some(module) => module,
+                    None => continue,
+                };
+                if tokens.next() != Some("import") {
+                    continue;
+                }
+                let level = module.len() - module.trim_start_matches('.').len();
+                let module = &module[level..];
+                if !module.is_empty() && !is_dotted_name(module) {
+                    continue;
+                }
+                let names = tokens
+                    .collect::<Vec<_>>()
+                    .join(" ")
+                    .split(',')
+                    .filter_map(|name| name.split_whitespace().next())
+                    .filter(|name| is_dotted_name(name) && !name.contains('.'))
+                    .map(String::from)
+                    .collect();
+                imports.push(Import {
+                    level,
+                    module: module.to_string(),
+                    names,
+                });
+            }
+            _ => {}
+        }
+    }
+    imports
+}
+
+/// Returns `true` if `name` is a module name such as `django.db`.
+fn is_dotted_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .split('.')
+            .all(|part| !part.is_empty() && part.chars().all(|c| c == '_' || c.is_alphanumeric()))
+}
+
+/// Splits the Python source `source` into simple statements.
+///
+/// Comments are removed, string literals are replaced by `""`, brackets are replaced by spaces
+/// and lines which are continued by brackets or backslashes are joined.  Compound statements
+/// on one line, such as `if x: import y`, are not split.
+fn python_statements(source: &str) -> Vec<String> {
+    let mut statements = Vec::new();
+    let mut statement = String::new();
+    let mut depth: usize = 0;
+    let mut characters = source.chars().peekable();
+
+    let mut end_statement = |statement: &mut String| {
+        let trimmed = statement.trim();
+        if !trimmed.is_empty() {
+            statements.push(trimmed.to_string());
+        }
+        statement.clear();
+    };
+    while let Some(character) = characters.next() {
+        match character {
+            '#' => while characters.next_if(|&c| c != '\n').is_some() {},
+            '\'' | '"' => {
+                let triple = characters.next_if_eq(&character).is_some();
+                if triple && characters.next_if_eq(&character).is_none() {
+                    // An empty string.
+                    statement.push_str("\"\"");
+                    continue;
+                }
+                let mut closing = 0;
+                while let Some(c) = characters.next() {
+                    if c == '\\' {
+                        characters.next();
+                        closing = 0;
+                    } else if c == character {
+                        closing += 1;
+                        if !triple || closing == 3 {
+                            break;
+                        }
+                    } else if c == '\n' && !triple {
+                        break;
+                    } else {
+                        closing = 0;
+                    }
+                }
+                statement.push_str("\"\"");
+            }
+            '\\' if characters.peek() == Some(&'\n') => {
+                characters.next();
+                statement.push(' ');
+            }
+            '(' | '[' | '{' => {
+                depth += 1;
+                statement.push(' ');
+            }
+            ')' | ']' | '}' => {
+                depth = depth.saturating_sub(1);
+                statement.push(' ');
+            }
+            '\n' | ';' if depth == 0 => end_statement(&mut statement),
+            '\n' => statement.push(' '),
+            character => statement.push(character),
+        }
+    }
+    end_statement(&mut statement);
+    statements
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::files_list::tests::test_tree::tree_with_contents;
+
+    fn import(level: usize, module: &str, names: &[&str]) -> Import {
+        Import {
+            level,
+            module: module.to_string(),
+            names: names.iter().map(|name| name.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn python_imports_of_statements() {
+        let source = r#"
+"""Views
+
+import docstring
+"""
+import os, sys as system
+import django.db.models  # import comment
+from django.http import (
+    HttpResponse,
+    JsonResponse as Json,
+)
+from . import forms; from .models import Article
+from ..utils import \
+    slugify
+from __future__ import annotations
+from .helpers import *
+
+def view(request):
+    import json
+    message = "from x import y"
+    return HttpResponse(message)
+"#;
+        assert_eq!(
+            vec![
+                import(0, "os", &[]),
+                import(0, "sys", &[]),
+                import(0, "django.db.models", &[]),
+                import(0, "django.http", &["HttpResponse", "JsonResponse"]),
+                import(1, "", &["forms"]),
+                import(1, "models", &["Article"]),
+                import(2, "utils", &["slugify"]),
+                import(0, "__future__", &["annotations"]),
+                import(1, "helpers", &[]),
+                import(0, "json", &[]),
+            ],
+            python_imports(source)
+        );
+    }
+
+    #[test]
+    fn python_statements_of_strings() {
+        assert_eq!(
+            vec![
+                "a = \"\"",
+                "b = \"\"",
+                "c = \"\" + \"\"",
+                "d = r\"\"",
+                "import e"
+            ],
+            python_statements(
+                "a = ''\nb = '''x\n'''\nc = 'it\\'s' + \"#\"\nd = r\"\\\\\"\nimport e"
+            )
+        );
+    }
+
+    #[test]
+    fn files_list_add_python_imported_modules() {
+        let (root, files_list_file) = tree_with_contents(
+            &[
+                (
+                    "opt/app/manage.py",
+                    "import os\nimport django.shortcuts\nfrom blog import views\n",
+                ),
+                ("opt/app/blog/__init__.py", ""),
+                ("opt/app/blog/__pycache__/models.cpython-39.pyc", ""),
+                ("opt/app/blog/__pycache__/models.pypy39.pyc", ""),
+                ("opt/app/blog/forms.py", ""),
+                ("opt/app/blog/models.py", ""),
+                ("opt/app/blog/unused.py", ""),
+                (
+                    "opt/app/blog/views.py",
+                    "from . import forms\nfrom .models import Post\n",
+                ),
+                (
+                    "opt/app-venv/lib/python3.9/site-packages/django/__init__.py",
+                    "",
+                ),
+                (
+                    "opt/app-venv/lib/python3.9/site-packages/django/http/__init__.py",
+                    "from django.http.response import HttpResponse\n",
+                ),
+                (
+                    "opt/app-venv/lib/python3.9/site-packages/django/http/response.py",
+                    "",
+                ),
+                (
+                    "opt/app-venv/lib/python3.9/site-packages/django/shortcuts.py",
+                    "from django.http import HttpResponse\n",
+                ),
+                (
+                    "usr/lib/python3.9/lib-dynload/_json.cpython-39-x86_64-linux-gnu.so",
+                    "",
+                ),
+                ("usr/lib/python3.9/os.py", "import posixpath, _json\n"),
+                ("usr/lib/python3.9/posixpath.py", ""),
+            ],
+            "opt/app-venv/lib/python3.9/site-packages/django/__init__.py\n\
+             opt/app/manage.py\n\
+             usr/lib/python3.9/os.py\n",
+        );
+
+        let mut files_list = FilesList::with_root(&files_list_file, root.path());
+        files_list.set_implementation_tags(&["cpython"]);
+        files_list.load().unwrap();
+        let added = files_list.add_python_imported_modules().unwrap();
+        assert_eq!(
+            vec![
+                "/opt/app-venv/lib/python3.9/site-packages/django/http/__init__.py",
+                "/opt/app-venv/lib/python3.9/site-packages/django/http/response.py",
+                "/opt/app-venv/lib/python3.9/site-packages/django/shortcuts.py",
+                "/opt/app/blog/__init__.py",
+                "/opt/app/blog/__pycache__/models.cpython-39.pyc",
+                "/opt/app/blog/forms.py",
+                "/opt/app/blog/models.py",
+                "/opt/app/blog/views.py",
+                "/usr/lib/python3.9/lib-dynload/_json.cpython-39-x86_64-linux-gnu.so",
+                "/usr/lib/python3.9/posixpath.py",
+            ],
+            added.into_iter().collect::<Vec<_>>()
+        );
+        assert!(files_list.add_python_imported_modules().unwrap().is_empty());
+    }
+}
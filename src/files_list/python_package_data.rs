@@ -0,0 +1,156 @@
+//! Data files, such as templates and translations, in the Python packages of a `FilesList`
+
+use std::collections::BTreeSet;
+
+use super::bytecode::{legacy_bytecode_source, CachedBytecode, PYCACHE_DIRECTORY, PY_EXTENSION};
+use super::FilesList;
+use crate::error::Error;
+use crate::glob;
+
+/// The glob patterns of the data files which `add_python_package_data` adds by default.
+pub const DEFAULT_PACKAGE_DATA_PATTERNS: [&str; 3] =
+    ["templates/**", "locale/**/*.mo", "static/**"];
+
+const INIT_MODULE: &str = "__init__";
+const PYTHON_EXTENSIONS: [&str; 3] = [".py", ".pyc", ".pyo"];
+
+impl FilesList {
+    /// Adds data files in listed Python packages to the files list file.
+    pub fn include_python_package_data<S: AsRef<str>>(
+        &mut self,
+        patterns: &[S],
+    ) -> Result<BTreeSet<String>, Error> {
+        self.load()?;
+        let added_data = self.add_python_package_data(patterns)?;
+        self.save()?;
+        Ok(added_data)
+    }
+
+    /// Adds data files in listed Python packages to the files list without writing the files list
+    /// file.
+    ///
+    /// Templates, static files and translations are often not opened during a short dev session.
+    /// A Python package is listed when its `__init__.py` or the bytecode of its `__init__.py` is
+    /// listed.  For each listed package, `add_python_package_data` adds the files beneath its
+    /// directory which match one of the glob patterns `patterns`, such as
+    /// `DEFAULT_PACKAGE_DATA_PATTERNS`.  Patterns are matched against paths relative to the
+    /// package directory, as described by `glob::matches`.  Python source and bytecode files are
+    /// never added.  The added files are returned with a leading `/`.
+    pub fn add_python_package_data<S: AsRef<str>>(
+        &mut self,
+        patterns: &[S],
+    ) -> Result<BTreeSet<String>, Error> {
+        let mut added_data: BTreeSet<String> = BTreeSet::new();
+        if patterns.is_empty() {
+            return Ok(added_data);
+        }
+
+        for package in self.python_packages() {
+            if !self.resolve(&package).is_dir() {
+                continue;
+            }
+            let prefix = format!("{}/", package);
+            for file in self.files_beneath(&package)? {
+                let relative = &file[prefix.len()..];
+                if relative
+                    .split('/')
+                    .any(|component| component == PYCACHE_DIRECTORY)
+                    || PYTHON_EXTENSIONS
+                        .iter()
+                        .any(|extension| relative.ends_with(extension))
+                    || self.contains(&file)
+                {
+                    continue;
+                }
+                if patterns
+                    .iter()
+                    .any(|pattern| glob::matches(pattern.as_ref(), relative))
+                {
+                    added_data.insert(format!("/{}", file));
+                }
+            }
+        }
+        self.insert_reachable_entries(&mut added_data)?;
+
+        Ok(added_data)
+    }
+
+    /// Returns the directories of the Python packages whose `__init__.py`, or its bytecode, is
+    /// listed.
+    fn python_packages(&self) -> BTreeSet<String> {
+        let init_source = format!("{}{}", INIT_MODULE, PY_EXTENSION);
+        let mut packages = BTreeSet::new();
+        for entry in self.entries() {
+            let source = match entry.rsplit_once('/') {
+                Some((directory, name)) => match directory.rsplit_once('/') {
+                    Some((package, PYCACHE_DIRECTORY))
+                        if CachedBytecode::parse(name)
+                            .is_some_and(|bytecode| bytecode.module == INIT_MODULE) =>
+                    {
+                        format!("{}/{}", package, init_source)
+                    }
+                    _ => legacy_bytecode_source(entry).unwrap_or_else(|| entry.to_string()),
+                },
+                None => continue,
+            };
+            if let Some(package) = source.strip_suffix(&format!("/{}", init_source)) {
+                packages.insert(package.to_string());
+            }
+        }
+        packages
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::files_list::tests::test_tree::tree;
+
+    #[test]
+    fn files_list_add_python_package_data() {
+        let (root, files_list_file) = tree(
+            &[
+                "opt/app-venv/lib/python3.9/site-packages/django/__init__.py",
+                "opt/app-venv/lib/python3.9/site-packages/django/conf/locale/de/LC_MESSAGES/django.mo",
+                "opt/app-venv/lib/python3.9/site-packages/django/conf/locale/de/LC_MESSAGES/django.po",
+                "opt/app-venv/lib/python3.9/site-packages/django/contrib/admin/__init__.py",
+                "opt/app-venv/lib/python3.9/site-packages/django/contrib/admin/static/admin/css/base.css",
+                "opt/app-venv/lib/python3.9/site-packages/django/contrib/admin/templates/admin/base.html",
+                "opt/app-venv/lib/python3.9/site-packages/django/contrib/admin/templates/admin/__init__.py",
+                "opt/app-venv/lib/python3.9/site-packages/django/contrib/auth/__init__.py",
+                "opt/app-venv/lib/python3.9/site-packages/django/contrib/auth/templates/registration/password_reset_email.html",
+                "opt/app-venv/lib/python3.9/site-packages/django/locale/fr/LC_MESSAGES/django.mo",
+                "opt/app-venv/lib/python3.9/site-packages/django/templates/__pycache__/cached.cpython-39.pyc",
+            ],
+            "opt/app-venv/lib/python3.9/site-packages/django/__init__.py\n\
+             opt/app-venv/lib/python3.9/site-packages/django/contrib/admin/__pycache__/__init__.cpython-39.pyc\n",
+        );
+
+        let mut files_list = FilesList::with_root(&files_list_file, root.path());
+        files_list.load().unwrap();
+        let added = files_list
+            .add_python_package_data(&DEFAULT_PACKAGE_DATA_PATTERNS)
+            .unwrap();
+        assert_eq!(
+            vec![
+                "/opt/app-venv/lib/python3.9/site-packages/django/contrib/admin/static/admin/css/base.css",
+                "/opt/app-venv/lib/python3.9/site-packages/django/contrib/admin/templates/admin/base.html",
+                "/opt/app-venv/lib/python3.9/site-packages/django/locale/fr/LC_MESSAGES/django.mo",
+            ],
+            added.into_iter().collect::<Vec<_>>()
+        );
+
+        let added = files_list
+            .add_python_package_data(&["conf/locale/**"])
+            .unwrap();
+        assert_eq!(
+            vec![
+                "/opt/app-venv/lib/python3.9/site-packages/django/conf/locale/de/LC_MESSAGES/django.mo",
+                "/opt/app-venv/lib/python3.9/site-packages/django/conf/locale/de/LC_MESSAGES/django.po",
+            ],
+            added.into_iter().collect::<Vec<_>>()
+        );
+    }
+}
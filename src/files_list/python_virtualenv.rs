@@ -0,0 +1,180 @@
+//! The infrastructure of the Python virtual environments, such as `pyvenv.cfg`, in a `FilesList`
+
+use std::collections::BTreeSet;
+
+use super::FilesList;
+use crate::error::Error;
+
+const BIN_DIRECTORY: &str = "bin";
+const LIB_DIRECTORIES: [&str; 2] = ["lib", "lib64"];
+const PTH_EXTENSION: &str = ".pth";
+const PYTHON_PREFIX: &str = "python";
+const PYVENV_CFG_FILE: &str = "pyvenv.cfg";
+const SITE_PACKAGES_DIRECTORY: &str = "site-packages";
+
+/// The files read by `site` from a `lib/pythonX.Y` directory, including those of legacy
+/// `virtualenv`.
+const STDLIB_SITE_FILES: [&str; 3] = ["site.py", "orig-prefix.txt", "no-global-site-packages.txt"];
+
+/// The modules imported by `site` from a `site-packages` directory, including the module which
+/// `virtualenv` installs beside its `.pth` file.
+const SITE_PACKAGES_SITE_FILES: [&str; 3] =
+    ["sitecustomize.py", "usercustomize.py", "_virtualenv.py"];
+
+impl FilesList {
+    /// Adds the files which start the listed Python virtual environments to the files list file.
+    pub fn include_python_virtualenv_files(&mut self) -> Result<BTreeSet<String>, Error> {
+        self.load()?;
+        let added_files = self.add_python_virtualenv_files()?;
+        self.save()?;
+        Ok(added_files)
+    }
+
+    /// Adds the files which start the listed Python virtual environments to the files list without
+    /// writing the files list file.
+    ///
+    /// A grain fails to start when its virtual environment is incomplete.  A virtual environment is
+    /// listed when a listed entry is beneath a directory which contains `pyvenv.cfg`.  For each
+    /// listed virtual environment, `add_python_virtualenv_files` adds
+    ///
+    /// - `pyvenv.cfg`,
+    /// - each `bin/python*` and the symbolic links from it to the base interpreter,
+    /// - the symbolic links `lib` and `lib64`, if they are symbolic links,
+    /// - `site.py` and the files of legacy `virtualenv` in each `lib/pythonX.Y` directory and
+    /// - each `.pth` file, `sitecustomize.py`, `usercustomize.py` and `_virtualenv.py` in each
+    ///   `site-packages` directory.
+    ///
+    /// The added files are returned with a leading `/`.
+    pub fn add_python_virtualenv_files(&mut self) -> Result<BTreeSet<String>, Error> {
+        let mut files: BTreeSet<String> = BTreeSet::new();
+
+        for virtualenv in self.python_virtualenvs() {
+            files.insert(format!("{}/{}", virtualenv, PYVENV_CFG_FILE));
+
+            let bin = format!("{}/{}", virtualenv, BIN_DIRECTORY);
+            for name in self.directory_names(&bin)? {
+                if name.starts_with(PYTHON_PREFIX) {
+                    files.extend(self.symlink_chain(&format!("{}/{}", bin, name))?);
+                }
+            }
+
+            for lib_name in LIB_DIRECTORIES.iter() {
+                let lib = format!("{}/{}", virtualenv, lib_name);
+                match self.resolve(&lib).symlink_metadata() {
+                    Ok(metadata) if metadata.file_type().is_symlink() => {
+                        files.extend(self.symlink_chain(&lib)?);
+                        continue;
+                    }
+                    Ok(metadata) if metadata.is_dir() => {}
+                    _ => continue,
+                }
+                for name in self.directory_names(&lib)? {
+                    if !name.starts_with(PYTHON_PREFIX) {
+                        continue;
+                    }
+                    let stdlib = format!("{}/{}", lib, name);
+                    for site_file in STDLIB_SITE_FILES.iter() {
+                        files.insert(format!("{}/{}", stdlib, site_file));
+                    }
+                    let site_packages = format!("{}/{}", stdlib, SITE_PACKAGES_DIRECTORY);
+                    for site_file in SITE_PACKAGES_SITE_FILES.iter() {
+                        files.insert(format!("{}/{}", site_packages, site_file));
+                    }
+                    for name in self.directory_names(&site_packages)? {
+                        if name.ends_with(PTH_EXTENSION) {
+                            files.insert(format!("{}/{}", site_packages, name));
+                        }
+                    }
+                }
+            }
+        }
+
+        let mut added_files: BTreeSet<String> = files
+            .into_iter()
+            .filter(|file| {
+                // Listing a directory would add everything beneath it.
+                !self.contains(file)
+                    && self
+                        .resolve(file)
+                        .symlink_metadata()
+                        .is_ok_and(|metadata| !metadata.is_dir())
+            })
+            .map(|file| format!("/{}", file))
+            .collect();
+        self.insert_reachable_entries(&mut added_files)?;
+
+        Ok(added_files)
+    }
+
+    /// Returns the directories of the Python virtual environments which contain listed entries.
+    fn python_virtualenvs(&self) -> BTreeSet<String> {
+        let mut checked: BTreeSet<&str> = BTreeSet::new();
+        let mut virtualenvs = BTreeSet::new();
+        for entry in self.entries() {
+            let mut directory = entry;
+            while let Some((parent, _)) = directory.rsplit_once('/') {
+                directory = parent;
+                if !checked.insert(directory) {
+                    break;
+                }
+                if self
+                    .resolve(&format!("{}/{}", directory, PYVENV_CFG_FILE))
+                    .is_file()
+                {
+                    virtualenvs.insert(directory.to_string());
+                }
+            }
+        }
+        virtualenvs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::files_list::tests::test_tree::tree;
+
+    #[test]
+    fn files_list_add_python_virtualenv_files() {
+        use std::os::unix::fs::symlink;
+
+        let (root, files_list_file) = tree(
+            &[
+                "opt/app-venv/pyvenv.cfg",
+                "opt/app-venv/lib/python3.9/site-packages/_virtualenv.pth",
+                "opt/app-venv/lib/python3.9/site-packages/_virtualenv.py",
+                "opt/app-venv/lib/python3.9/site-packages/distutils-precedence.pth",
+                "opt/app-venv/lib/python3.9/site-packages/django/__init__.py",
+                "usr/bin/python3.9",
+            ],
+            "opt/app-venv/lib/python3.9/site-packages/django/__init__.py\n",
+        );
+        let venv = root.path().join("opt/app-venv");
+        std::fs::create_dir(venv.join("bin")).unwrap();
+        symlink("/usr/bin/python3", venv.join("bin/python")).unwrap();
+        symlink("python", venv.join("bin/python3")).unwrap();
+        symlink("python3.9", root.path().join("usr/bin/python3")).unwrap();
+        symlink("lib", venv.join("lib64")).unwrap();
+
+        let mut files_list = FilesList::with_root(&files_list_file, root.path());
+        files_list.load().unwrap();
+        let added = files_list.add_python_virtualenv_files().unwrap();
+        assert_eq!(
+            vec![
+                "/opt/app-venv/bin/python",
+                "/opt/app-venv/bin/python3",
+                "/opt/app-venv/lib/python3.9/site-packages/_virtualenv.pth",
+                "/opt/app-venv/lib/python3.9/site-packages/_virtualenv.py",
+                "/opt/app-venv/lib/python3.9/site-packages/distutils-precedence.pth",
+                "/opt/app-venv/lib64",
+                "/opt/app-venv/pyvenv.cfg",
+                "/usr/bin/python3",
+                "/usr/bin/python3.9",
+            ],
+            added.into_iter().collect::<Vec<_>>()
+        );
+        assert!(files_list.add_python_virtualenv_files().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,317 @@
+//! Shared libraries for the ELF executables and shared objects in a `FilesList`
+
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::io::{ErrorKind, Read};
+
+use super::elf::{ld_so_cache_libraries, ElfFile, ElfIdentity};
+use super::{join, parent_of, FilesList};
+use crate::error::Error;
+
+const LD_SO_CACHE_FILE: &str = "etc/ld.so.cache";
+const ORIGIN_TOKENS: [&str; 2] = ["$ORIGIN", "${ORIGIN}"];
+/// The directories searched by the dynamic linker after its cache.
+const TRUSTED_DIRECTORIES: [&str; 2] = ["lib", "usr/lib"];
+const TRUSTED_DIRECTORIES_64_BIT: [&str; 4] = ["lib64", "usr/lib64", "lib", "usr/lib"];
+
+/// The result of `FilesList::add_shared_libraries`.
+#[derive(Debug, Default, PartialEq)]
+pub struct SharedLibraryFiles {
+    /// The shared libraries and symbolic links which were added, with a leading `/`.
+    pub added: BTreeSet<String>,
+    /// The libraries and interpreters which were not found, by the listed file or added library
+    /// which needs them, with a leading `/`.
+    pub missing: BTreeMap<String, BTreeSet<String>>,
+}
+
+impl FilesList {
+    /// Adds the shared libraries needed by listed ELF files to the files list file.
+    pub fn include_shared_libraries(&mut self) -> Result<SharedLibraryFiles, Error> {
+        self.load()?;
+        let shared_libraries = self.add_shared_libraries()?;
+        self.save()?;
+        Ok(shared_libraries)
+    }
+
+    /// Adds the shared libraries needed by listed ELF files to the files list without writing the
+    /// files list file.
+    ///
+    /// Executables and shared objects, such as `bin/bash` or compiled Python extensions, need
+    /// their `DT_NEEDED` libraries and their interpreter, the dynamic linker.  For each listed ELF
+    /// file, `add_shared_libraries` finds each library the way the dynamic linker does: in the
+    /// directories of `DT_RPATH`, or of `DT_RUNPATH` if it is present, with `$ORIGIN` replaced by
+    /// the directory of the ELF file, then in `etc/ld.so.cache` and then in `lib64`, `usr/lib64`,
+    /// `lib` and `usr/lib`.  Only libraries of the same class and machine as the ELF file are
+    /// found.  Each library is added with the symbolic links which lead to it, and the added
+    /// libraries need libraries in turn.
+    ///
+    /// All paths, and the symbolic links in them, are resolved beneath the root.  Libraries which
+    /// are not found are reported in `missing`.
+    pub fn add_shared_libraries(&mut self) -> Result<SharedLibraryFiles, Error> {
+        let cache = self.ld_so_cache()?;
+        let mut shared_libraries = SharedLibraryFiles::default();
+        let mut objects: VecDeque<String> = self.entries().map(String::from).collect();
+        let mut visited: BTreeSet<String> = objects.iter().cloned().collect();
+
+        while let Some(object) = objects.pop_front() {
+            let identity = match self.elf_identity(&object)? {
+                Some(identity) => identity,
+                None => continue,
+            };
+            let path = match self.resolve_beneath_root(&object)? {
+                Some(path) => path,
+                None => continue,
+            };
+            let elf_file = match ElfFile::parse(&std::fs::read(path)?) {
+                Some(elf_file) => elf_file,
+                None => continue,
+            };
+
+            let mut libraries = Vec::new();
+            let mut missing = BTreeSet::new();
+            if let Some(interpreter) = elf_file.interpreter.as_ref() {
+                match FilesList::normalize_path(interpreter) {
+                    Some(entry) if self.is_file_beneath_root(&entry)? => libraries.push(entry),
+                    _ => {
+                        missing.insert(interpreter.clone());
+                    }
+                }
+            }
+            for name in elf_file.needed.iter() {
+                match self.find_shared_library(name, &object, &elf_file, identity, &cache)? {
+                    Some(entry) => libraries.push(entry),
+                    None => {
+                        missing.insert(name.clone());
+                    }
+                }
+            }
+            if !missing.is_empty() {
+                shared_libraries
+                    .missing
+                    .insert(format!("/{}", object), missing);
+            }
+
+            for library in libraries {
+                let chain = self.symlink_chain(&library)?;
+                for entry in chain.iter() {
+                    if self.insert_reachable(entry)? {
+                        shared_libraries.added.insert(format!("/{}", entry));
+                    }
+                }
+                if let Some(target) = chain.last() {
+                    if visited.insert(target.clone()) {
+                        objects.push_back(target.clone());
+                    }
+                }
+            }
+        }
+
+        Ok(shared_libraries)
+    }
+
+    /// Returns the entry of the library `name` needed by the ELF file `object`, or `None` if it
+    /// is not found.
+    fn find_shared_library(
+        &self,
+        name: &str,
+        object: &str,
+        elf_file: &ElfFile,
+        identity: ElfIdentity,
+        cache: &BTreeMap<String, Vec<String>>,
+    ) -> Result<Option<String>, Error> {
+        let mut candidates: Vec<String> = Vec::new();
+        if name.contains('/') {
+            candidates.push(name.to_string());
+        } else {
+            let origin = format!("/{}", parent_of(object));
+            let directories = if elf_file.runpath.is_empty() {
+                &elf_file.rpath
+            } else {
+                &elf_file.runpath
+            };
+            for directory in directories {
+                let directory = ORIGIN_TOKENS
+                    .iter()
+                    .fold(directory.clone(), |directory, token| {
+                        directory.replace(token, &origin)
+                    });
+                candidates.push(format!("{}/{}", directory, name));
+            }
+            if let Some(paths) = cache.get(name) {
+                candidates.extend(paths.iter().cloned());
+            }
+            let trusted_directories: &[&str] = if identity.is_64_bit {
+                &TRUSTED_DIRECTORIES_64_BIT
+            } else {
+                &TRUSTED_DIRECTORIES
+            };
+            for directory in trusted_directories {
+                candidates.push(join(directory, name));
+            }
+        }
+
+        for candidate in candidates {
+            if let Some(entry) = FilesList::normalize_path(&candidate) {
+                if self.elf_identity(&entry)? == Some(identity) {
+                    return Ok(Some(entry));
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Returns `true` if `entry` is a regular file, or a symbolic link to one, beneath the root.
+    fn is_file_beneath_root(&self, entry: &str) -> Result<bool, Error> {
+        Ok(self
+            .resolve_beneath_root(entry)?
+            .is_some_and(|path| path.is_file()))
+    }
+
+    /// Returns the identity of the ELF file `entry`, or `None` if it is not a regular ELF file.
+    fn elf_identity(&self, entry: &str) -> Result<Option<ElfIdentity>, Error> {
+        let path = match self.resolve_beneath_root(entry)? {
+            Some(path) if path.is_file() => path,
+            _ => return Ok(None),
+        };
+        let mut header = Vec::new();
+        match std::fs::File::open(&path) {
+            Ok(file) => file.take(64).read_to_end(&mut header)?,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+        Ok(ElfIdentity::parse(&header))
+    }
+
+    /// Returns the paths of the libraries in `etc/ld.so.cache` beneath the root, by name.
+    fn ld_so_cache(&self) -> Result<BTreeMap<String, Vec<String>>, Error> {
+        let path = match self.resolve_beneath_root(LD_SO_CACHE_FILE)? {
+            Some(path) => path,
+            None => return Ok(BTreeMap::new()),
+        };
+        let data = match std::fs::read(path) {
+            Ok(data) => data,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(BTreeMap::new()),
+            Err(err) => return Err(err.into()),
+        };
+        let mut cache: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for (name, path) in ld_so_cache_libraries(&data) {
+            cache.entry(name).or_default().push(path);
+        }
+        Ok(cache)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::files_list::tests::test_elf::{elf_file, ld_so_cache};
+    use crate::files_list::tests::test_tree::tree_with_contents;
+
+    #[test]
+    fn files_list_add_shared_libraries() {
+        use std::os::unix::fs::symlink;
+
+        let (root, files_list_file) = tree_with_contents(
+            &[
+                (
+                    "bin/app",
+                    elf_file(
+                        Some("/lib64/ld-linux-x86-64.so.2"),
+                        &["libfoo.so.1", "libc.so.6"],
+                        Some("$ORIGIN/../lib/app"),
+                    ),
+                ),
+                (
+                    "etc/ld.so.cache",
+                    ld_so_cache(&[
+                        ("libc.so.6", "/lib/x86_64-linux-gnu/libc.so.6"),
+                        ("libz.so.1", "/lib/x86_64-linux-gnu/libz.so.1"),
+                    ]),
+                ),
+                (
+                    "lib/app/libfoo.so.1.2",
+                    elf_file(None, &["libz.so.1"], None),
+                ),
+                (
+                    "lib/x86_64-linux-gnu/ld-linux-x86-64.so.2",
+                    elf_file(None, &[], None),
+                ),
+                ("lib/x86_64-linux-gnu/libc.so.6", elf_file(None, &[], None)),
+                ("usr/lib/libm.so.6", elf_file(None, &[], None)),
+            ],
+            "bin/app\n",
+        );
+        symlink("libfoo.so.1.2", root.path().join("lib/app/libfoo.so.1")).unwrap();
+        std::fs::create_dir(root.path().join("lib64")).unwrap();
+        symlink(
+            "../lib/x86_64-linux-gnu/ld-linux-x86-64.so.2",
+            root.path().join("lib64/ld-linux-x86-64.so.2"),
+        )
+        .unwrap();
+
+        let mut files_list = FilesList::with_root(&files_list_file, root.path());
+        files_list.load().unwrap();
+        let shared_libraries = files_list.add_shared_libraries().unwrap();
+        assert_eq!(
+            vec![
+                "/lib/app/libfoo.so.1",
+                "/lib/app/libfoo.so.1.2",
+                "/lib/x86_64-linux-gnu/ld-linux-x86-64.so.2",
+                "/lib/x86_64-linux-gnu/libc.so.6",
+                "/lib64/ld-linux-x86-64.so.2",
+            ],
+            shared_libraries.added.into_iter().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            BTreeMap::from([(
+                "/lib/app/libfoo.so.1.2".to_string(),
+                BTreeSet::from(["libz.so.1".to_string()])
+            )]),
+            shared_libraries.missing
+        );
+    }
+
+    #[test]
+    fn files_list_add_shared_libraries_through_absolute_symlinks() {
+        use std::os::unix::fs::symlink;
+
+        let (root, files_list_file) = tree_with_contents(
+            &[
+                (
+                    "bin/tool",
+                    elf_file(
+                        Some("/lib64/ld-tool.so.2"),
+                        &["libbar.so.1"],
+                        Some("/usr/lib/tool"),
+                    ),
+                ),
+                ("opt/tool/ld-tool.so.2", elf_file(None, &[], None)),
+                ("opt/tool/lib/libbar.so.1", elf_file(None, &[], None)),
+            ],
+            "bin/tool\n",
+        );
+        std::fs::create_dir_all(root.path().join("lib64")).unwrap();
+        symlink(
+            "/opt/tool/ld-tool.so.2",
+            root.path().join("lib64/ld-tool.so.2"),
+        )
+        .unwrap();
+        std::fs::create_dir_all(root.path().join("usr/lib")).unwrap();
+        symlink("/opt/tool/lib", root.path().join("usr/lib/tool")).unwrap();
+
+        let mut files_list = FilesList::with_root(&files_list_file, root.path());
+        files_list.load().unwrap();
+        let shared_libraries = files_list.add_shared_libraries().unwrap();
+        assert_eq!(
+            vec![
+                "/lib64/ld-tool.so.2",
+                "/opt/tool/ld-tool.so.2",
+                "/opt/tool/lib/libbar.so.1",
+                "/usr/lib/tool",
+            ],
+            shared_libraries.added.into_iter().collect::<Vec<_>>()
+        );
+        assert_eq!(BTreeMap::new(), shared_libraries.missing);
+    }
+}
@@ -0,0 +1,247 @@
+//! Symbolic links in a `FilesList`
+
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+use super::FilesList;
+use crate::error::Error;
+
+/// The most symbolic links followed to resolve one entry, as for `ELOOP` on Linux.
+const MAX_SYMLINKS: usize = 40;
+
+/// An entry with its symbolic links resolved by `FilesList::resolve_symlinks`.
+pub(crate) struct ResolvedEntry {
+    /// The entries of the symbolic links which were followed, in order.
+    pub links: Vec<String>,
+    /// The entry with no symbolic links in it.
+    pub target: String,
+    /// `true` if `target` exists beneath the root.
+    pub exists: bool,
+}
+
+impl FilesList {
+    /// Adds the symbolic links in listed entries and their targets to the files list file.
+    pub fn include_symlink_targets(&mut self) -> Result<BTreeSet<String>, Error> {
+        self.load()?;
+        let added_entries = self.add_symlink_targets()?;
+        self.save()?;
+        Ok(added_entries)
+    }
+
+    /// Adds the symbolic links in listed entries and their targets to the files list without
+    /// writing the files list file.
+    ///
+    /// A Sandstorm package holds symbolic links, not the files they point to.  For each listed
+    /// entry, `add_symlink_targets` follows each symbolic link in the entry, one component at a
+    /// time and beneath the root, and adds every link it follows and the final target.  A target
+    /// which is a directory is not added, because listing a directory adds everything beneath it.
+    /// The added entries are returned with a leading `/`.
+    ///
+    /// If a symbolic link in a listed entry has a target which does not exist, no entries are added
+    /// and `Error::DanglingSymlinks` reports each such link and its target.  A listed entry which
+    /// does not exist beneath a link to an existing directory, such as `lib/missing.so` for
+    /// `lib -> usr/lib`, is skipped, as `prune` removes it.
+    pub fn add_symlink_targets(&mut self) -> Result<BTreeSet<String>, Error> {
+        let mut added_entries: BTreeSet<String> = BTreeSet::new();
+        let mut dangling: BTreeMap<String, String> = BTreeMap::new();
+
+        for entry in self.entries() {
+            let resolved = self.resolve_symlinks(entry)?;
+            if resolved.links.is_empty() {
+                continue;
+            }
+            if !resolved.exists {
+                if let Some(link) = resolved.links.last() {
+                    let link_target = self.resolve_symlinks(link)?;
+                    if !link_target.exists {
+                        dangling.insert(format!("/{}", link), format!("/{}", link_target.target));
+                    }
+                }
+                continue;
+            }
+            let target_is_directory = self.resolve(&resolved.target).is_dir();
+            for link in resolved.links.into_iter() {
+                if !self.contains(&link) {
+                    added_entries.insert(format!("/{}", link));
+                }
+            }
+            if !target_is_directory && !self.contains(&resolved.target) {
+                added_entries.insert(format!("/{}", resolved.target));
+            }
+        }
+        if !dangling.is_empty() {
+            return Err(Error::DanglingSymlinks(dangling));
+        }
+        self.insert_reachable_entries(&mut added_entries)?;
+
+        Ok(added_entries)
+    }
+
+    /// Resolves each symbolic link in `entry`, one component at a time.
+    ///
+    /// Absolute targets are resolved from the root and `..` never leaves the root, as in a
+    /// chroot.  A link which leads to itself, or past `MAX_SYMLINKS` links, does not exist.
+    pub(crate) fn resolve_symlinks(&self, entry: &str) -> Result<ResolvedEntry, Error> {
+        let mut pending: VecDeque<String> = entry.split('/').map(String::from).collect();
+        let mut resolved: Vec<String> = Vec::new();
+        let mut links: Vec<String> = Vec::new();
+        let mut exists = true;
+
+        while let Some(component) = pending.pop_front() {
+            match component.as_str() {
+                "" | "." => continue,
+                ".." => {
+                    resolved.pop();
+                    continue;
+                }
+                _ => {}
+            }
+            resolved.push(component);
+            let current = resolved.join("/");
+            let path = self.resolve(&current);
+            let metadata = match path.symlink_metadata() {
+                Ok(metadata) => metadata,
+                Err(err)
+                    if matches!(err.kind(), ErrorKind::NotFound | ErrorKind::NotADirectory) =>
+                {
+                    exists = false;
+                    resolved.extend(pending.drain(..));
+                    break;
+                }
+                Err(err) => return Err(err.into()),
+            };
+            if !metadata.file_type().is_symlink() {
+                continue;
+            }
+            if links.len() == MAX_SYMLINKS {
+                exists = false;
+                resolved.extend(pending.drain(..));
+                break;
+            }
+            links.push(current);
+            let target = std::fs::read_link(&path)?;
+            let target = match target.to_str() {
+                Some(target) => target.to_string(),
+                None => {
+                    return Err(Error::UnableToProcessNonUtf8Path(
+                        target.to_string_lossy().into_owned(),
+                    ))
+                }
+            };
+            resolved.pop();
+            if target.starts_with('/') {
+                resolved.clear();
+            }
+            for component in target.split('/').rev() {
+                pending.push_front(component.to_string());
+            }
+        }
+
+        Ok(ResolvedEntry {
+            links,
+            target: FilesList::normalize_path(&resolved.join("/"))
+                .unwrap_or_else(|| resolved.join("/")),
+            exists,
+        })
+    }
+
+    /// Returns the path on the host of the file `entry` points to, with the symbolic links in
+    /// `entry` resolved beneath the root as described by `resolve_symlinks`, or `None` if the file
+    /// does not exist.
+    ///
+    /// Unlike `resolve`, the returned path has no symbolic links in it, so that the host never
+    /// follows an absolute link out of the root.
+    pub(crate) fn resolve_beneath_root(&self, entry: &str) -> Result<Option<PathBuf>, Error> {
+        let resolved = self.resolve_symlinks(entry)?;
+        Ok(if resolved.exists {
+            Some(self.resolve(&resolved.target))
+        } else {
+            None
+        })
+    }
+
+    /// Returns the entries of the symbolic links which lead from `entry` to the file it points
+    /// to, in order, followed by that file.
+    ///
+    /// The links are resolved as described by `resolve_symlinks`.  If the file does not exist,
+    /// only the links are returned.  If `entry` has no symbolic links in it, the chain is just
+    /// `entry`.
+    pub(crate) fn symlink_chain(&self, entry: &str) -> Result<Vec<String>, Error> {
+        let resolved = self.resolve_symlinks(entry)?;
+        let mut chain = resolved.links;
+        if resolved.exists {
+            chain.push(resolved.target);
+        }
+        Ok(chain)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::files_list::tests::test_tree::tree;
+
+    #[test]
+    fn files_list_add_symlink_targets() {
+        use std::os::unix::fs::symlink;
+
+        let (root, files_list_file) = tree(
+            &[
+                "opt/app/releases/2/app.py",
+                "usr/bin/python3.9",
+                "usr/lib/x86_64-linux-gnu/libc.so.6",
+                "usr/lib/x86_64-linux-gnu/libz.so.1.2",
+            ],
+            "lib/x86_64-linux-gnu/libc.so.6\n\
+             lib/x86_64-linux-gnu/libz.so.1\n\
+             lib/x86_64-linux-gnu/missing.so\n\
+             opt/app/current/app.py\n\
+             usr/bin/broken\n\
+             usr/bin/escape\n\
+             usr/bin/python3\n",
+        );
+        for (target, link) in [
+            ("usr/lib", "lib"),
+            ("libz.so.1.2", "usr/lib/x86_64-linux-gnu/libz.so.1"),
+            ("/usr/bin/python3.9", "usr/bin/python3"),
+            ("../../../../usr/bin/python3.9", "usr/bin/escape"),
+            ("missing", "usr/bin/broken"),
+            ("../../opt/app/releases/2", "opt/app/current"),
+        ] {
+            symlink(target, root.path().join(link)).unwrap();
+        }
+
+        let mut files_list = FilesList::with_root(&files_list_file, root.path());
+        files_list.load().unwrap();
+        match files_list.add_symlink_targets() {
+            Err(Error::DanglingSymlinks(links)) => assert_eq!(
+                BTreeMap::from([(
+                    "/usr/bin/broken".to_string(),
+                    "/usr/bin/missing".to_string()
+                )]),
+                links
+            ),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+        assert_eq!(7, files_list.entries().count());
+
+        files_list.remove("usr/bin/broken");
+        let added = files_list.add_symlink_targets().unwrap();
+        assert_eq!(
+            vec![
+                "/lib",
+                "/opt/app/current",
+                "/opt/app/releases/2/app.py",
+                "/usr/bin/python3.9",
+                "/usr/lib/x86_64-linux-gnu/libc.so.6",
+                "/usr/lib/x86_64-linux-gnu/libz.so.1",
+                "/usr/lib/x86_64-linux-gnu/libz.so.1.2",
+            ],
+            added.into_iter().collect::<Vec<_>>()
+        );
+        assert!(files_list.add_symlink_targets().unwrap().is_empty());
+    }
+}
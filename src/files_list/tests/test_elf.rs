@@ -0,0 +1,103 @@
+//! Builders for minimal ELF files and `ld.so.cache` files
+use std::convert::TryFrom;
+
+const ADDRESS: u64 = 0x40_0000;
+const HEADER_SIZE: usize = 64;
+const PROGRAM_HEADER_SIZE: usize = 56;
+
+/// Returns a 64-bit little-endian x86-64 shared object with a dynamic section.
+pub fn elf_file(interpreter: Option<&str>, needed: &[&str], runpath: Option<&str>) -> Vec<u8> {
+    let segments = if interpreter.is_some() { 3 } else { 2 };
+    let interpreter_offset = HEADER_SIZE + segments * PROGRAM_HEADER_SIZE;
+    let interpreter = interpreter.map(|interpreter| format!("{}\0", interpreter));
+    let interpreter_size = interpreter.as_ref().map_or(0, String::len);
+
+    let mut strings = vec![0u8];
+    let mut dynamic: Vec<(u64, u64)> = Vec::new();
+    for name in needed {
+        dynamic.push((1, strings.len() as u64));
+        strings.extend_from_slice(name.as_bytes());
+        strings.push(0);
+    }
+    if let Some(runpath) = runpath {
+        dynamic.push((29, strings.len() as u64));
+        strings.extend_from_slice(runpath.as_bytes());
+        strings.push(0);
+    }
+    let strings_offset = interpreter_offset + interpreter_size;
+    dynamic.push((5, ADDRESS + strings_offset as u64));
+    dynamic.push((0, 0));
+    let dynamic_offset = (strings_offset + strings.len() + 7) & !7;
+    let size = dynamic_offset + dynamic.len() * 16;
+
+    let mut data = vec![0u8; size];
+    data[..8].copy_from_slice(b"\x7fELF\x02\x01\x01\x00");
+    put(&mut data, 16, &3u16.to_le_bytes());
+    put(&mut data, 18, &62u16.to_le_bytes());
+    put(&mut data, 20, &1u32.to_le_bytes());
+    put(&mut data, 32, &(HEADER_SIZE as u64).to_le_bytes());
+    put(&mut data, 52, &(HEADER_SIZE as u16).to_le_bytes());
+    put(&mut data, 54, &(PROGRAM_HEADER_SIZE as u16).to_le_bytes());
+    put(
+        &mut data,
+        56,
+        &u16::try_from(segments).unwrap().to_le_bytes(),
+    );
+
+    let mut program_headers = vec![
+        (1u32, 0usize, size),
+        (2, dynamic_offset, dynamic.len() * 16),
+    ];
+    if let Some(interpreter) = interpreter.as_ref() {
+        program_headers.push((3, interpreter_offset, interpreter.len()));
+        put(&mut data, interpreter_offset, interpreter.as_bytes());
+    }
+    for (index, (kind, offset, size)) in program_headers.into_iter().enumerate() {
+        let header = HEADER_SIZE + index * PROGRAM_HEADER_SIZE;
+        put(&mut data, header, &kind.to_le_bytes());
+        put(&mut data, header + 8, &(offset as u64).to_le_bytes());
+        put(
+            &mut data,
+            header + 16,
+            &(ADDRESS + offset as u64).to_le_bytes(),
+        );
+        put(&mut data, header + 32, &(size as u64).to_le_bytes());
+        put(&mut data, header + 40, &(size as u64).to_le_bytes());
+    }
+    put(&mut data, strings_offset, &strings);
+    for (index, (tag, value)) in dynamic.into_iter().enumerate() {
+        put(&mut data, dynamic_offset + index * 16, &tag.to_le_bytes());
+        put(
+            &mut data,
+            dynamic_offset + index * 16 + 8,
+            &value.to_le_bytes(),
+        );
+    }
+    data
+}
+
+/// Returns an `ld.so.cache` file in the format of glibc 2.32 and later.
+pub fn ld_so_cache(libraries: &[(&str, &str)]) -> Vec<u8> {
+    let strings_offset = 48 + libraries.len() * 24;
+    let mut strings: Vec<u8> = Vec::new();
+    let mut data = vec![0u8; strings_offset];
+    data[..20].copy_from_slice(b"glibc-ld.so.cache1.1");
+    put(&mut data, 20, &(libraries.len() as u32).to_ne_bytes());
+    for (index, (name, path)) in libraries.iter().enumerate() {
+        let entry = 48 + index * 24;
+        put(&mut data, entry, &0x0303i32.to_ne_bytes());
+        for (field, string) in [(4, name), (8, path)] {
+            let offset = (strings_offset + strings.len()) as u32;
+            put(&mut data, entry + field, &offset.to_ne_bytes());
+            strings.extend_from_slice(string.as_bytes());
+            strings.push(0);
+        }
+    }
+    put(&mut data, 24, &(strings.len() as u32).to_ne_bytes());
+    data.extend_from_slice(&strings);
+    data
+}
+
+fn put(data: &mut [u8], offset: usize, bytes: &[u8]) {
+    data[offset..offset + bytes.len()].copy_from_slice(bytes);
+}
@@ -0,0 +1,33 @@
+//! Builders for temporary roots with files and a files list
+use std::path::{Path, PathBuf};
+
+use tempfile::TempDir;
+
+/// Returns a temporary root with an empty file for each of `files`, and the path of the files
+/// list file `sandstorm-files.list` in the root, which holds `files_list`.
+pub fn tree(files: &[&str], files_list: &str) -> (TempDir, PathBuf) {
+    let files: Vec<(&str, &str)> = files.iter().map(|file| (*file, "")).collect();
+    tree_with_contents(&files, files_list)
+}
+
+/// Returns a temporary root with each of `files` and its contents, and the path of the files list
+/// file `sandstorm-files.list` in the root, which holds `files_list`.
+pub fn tree_with_contents<C: AsRef<[u8]>>(
+    files: &[(&str, C)],
+    files_list: &str,
+) -> (TempDir, PathBuf) {
+    let root = tempfile::tempdir().unwrap();
+    for (file, contents) in files {
+        write_file(root.path(), file, contents);
+    }
+    let files_list_file = root.path().join("sandstorm-files.list");
+    std::fs::write(&files_list_file, files_list).unwrap();
+    (root, files_list_file)
+}
+
+/// Writes `contents` to `file` beneath `root`, creating its parent directories.
+pub fn write_file<C: AsRef<[u8]>>(root: &Path, file: &str, contents: C) {
+    let path = root.join(file);
+    std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+    std::fs::write(path, contents).unwrap();
+}
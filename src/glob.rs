@@ -0,0 +1,99 @@
+//! Glob
+//!
+//! `glob` matches `/`-separated paths against shell-style patterns
+
+/// Returns `true` if `path` matches the glob `pattern`.
+///
+/// `*` matches any characters except `/`, `?` matches one character except `/` and a `**`
+/// component matches any number of components.  A pattern without a `/`, such as `*.pyi`, is
+/// matched against the last component of `path`; any other pattern, such as `tests/**`, is matched
+/// against the whole of `path`.  Leading `/`s are ignored.
+pub fn matches(pattern: &str, path: &str) -> bool {
+    let pattern = pattern.trim_start_matches('/');
+    let path = path.trim_start_matches('/');
+    if pattern.contains('/') {
+        let pattern: Vec<&str> = pattern.split('/').collect();
+        let path: Vec<&str> = path.split('/').collect();
+        matches_components(&pattern, &path)
+    } else {
+        match path.rsplit('/').next() {
+            Some(name) => matches_component(pattern, name),
+            None => false,
+        }
+    }
+}
+
+fn matches_components(pattern: &[&str], path: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((&"**", rest)) => (0..=path.len()).any(|skip| matches_components(rest, &path[skip..])),
+        Some((component, rest)) => match path.split_first() {
+            Some((name, path)) => {
+                matches_component(component, name) && matches_components(rest, path)
+            }
+            None => false,
+        },
+    }
+}
+
+fn matches_component(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    // The position of the last `*` and the position in `name` which it matched up to.
+    let mut star: Option<(usize, usize)> = None;
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, n));
+            p += 1;
+        } else if let Some((star_p, star_n)) = star {
+            p = star_p + 1;
+            n = star_n + 1;
+            star = Some((star_p, star_n + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_names() {
+        assert!(matches("*.pyi", "django/db/models/__init__.pyi"));
+        assert!(matches("test_?.py", "app/test_a.py"));
+        assert!(matches("*", "README"));
+        assert!(!matches("*.pyi", "django/db/models/__init__.py"));
+        assert!(!matches("test_?.py", "app/test_ab.py"));
+    }
+
+    #[test]
+    fn matches_paths() {
+        assert!(matches(
+            "django/*/tests/*.py",
+            "django/contrib/tests/test_admin.py"
+        ));
+        assert!(matches("**/tests/**", "tests/test_admin.py"));
+        assert!(matches(
+            "**/tests/**",
+            "django/contrib/admin/tests/data/a.json"
+        ));
+        assert!(matches(
+            "/locale/**/*.mo",
+            "locale/de/LC_MESSAGES/django.mo"
+        ));
+        assert!(matches("docs/**", "docs"));
+        assert!(!matches("django/*.py", "django/contrib/admin.py"));
+        assert!(!matches("**/tests/**", "django/contrib/testsuite/a.py"));
+        assert!(!matches(
+            "locale/**/*.mo",
+            "locale/de/LC_MESSAGES/django.po"
+        ));
+    }
+}
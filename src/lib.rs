@@ -1,3 +1,8 @@
 //! A library and tool to help package applications for [Sandstorm](https://sandstorm.io)
+pub mod cli;
+pub mod diff;
 pub mod error;
 pub mod files_list;
+pub mod glob;
+pub mod pkgdef;
+pub mod spk;
@@ -0,0 +1,984 @@
+//! Package definition
+//!
+//! `pkgdef` reads and edits `sandstorm-pkgdef.capnp`, the package definition of a Sandstorm
+//! application, which is written in the [Cap'n Proto](https://capnproto.org/language.html) text
+//! format.  The fields of the package definition which decide the files of the package are parsed
+//! into Rust values.  When the package definition is written, only the fields whose values were
+//! changed are rewritten; the rest of the file, including its comments, is kept as it was read.
+
+use std::fmt::{Display, Formatter};
+use std::fs::File;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use crate::error::Error;
+
+pub const DEFAULT_PKGDEF_PATH: &str = "./.sandstorm/sandstorm-pkgdef.capnp";
+pub const PKGDEF_FILE_NAME: &str = "sandstorm-pkgdef.capnp";
+
+/// The name of the Cap'n Proto type of the package definition constant.
+const PACKAGE_DEFINITION_TYPE: &str = "PackageDefinition";
+const ID_FIELD: &str = "id";
+const ALWAYS_INCLUDE_FIELD: &str = "alwaysInclude";
+const FILE_LIST_FIELD: &str = "fileList";
+const SOURCE_MAP_FIELD: &str = "sourceMap";
+const SEARCH_PATH_FIELD: &str = "searchPath";
+const PACKAGE_PATH_FIELD: &str = "packagePath";
+const SOURCE_PATH_FIELD: &str = "sourcePath";
+const HIDE_PATHS_FIELD: &str = "hidePaths";
+
+const INDENT: &str = "  ";
+/// Values which would make a line longer than this are written over several lines.
+const MAX_LINE_LENGTH: usize = 100;
+
+/// The `sourceMap` of a package definition, which maps paths in the package to paths on the
+/// host.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SourceMap {
+    /// The mappings, in the order in which `spk` searches them.
+    pub search_path: Vec<Mapping>,
+}
+
+/// A mapping in `SourceMap::search_path`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Mapping {
+    /// The path in the package, such as `opt/app`; empty for the root of the package.
+    pub package_path: String,
+    /// The path on the host, relative to the directory of the package definition file.
+    pub source_path: String,
+    /// The paths beneath `source_path` which are hidden from the package.
+    pub hide_paths: Vec<String>,
+}
+
+/// A parsed `sandstorm-pkgdef.capnp` file.
+///
+/// The public fields hold the values read by `parse`.  They may be changed, and `to_string` and
+/// `write` rewrite the fields which were changed.
+#[derive(Debug)]
+pub struct Pkgdef {
+    text: String,
+    definition: StructValue,
+    id: Option<String>,
+    parsed_always_include: Vec<String>,
+    parsed_file_list: Option<String>,
+    parsed_source_map: SourceMap,
+    /// The paths which `spk` adds to the package with everything beneath them.
+    pub always_include: Vec<String>,
+    /// The path of the files list, relative to the directory of the package definition file.
+    pub file_list: Option<String>,
+    pub source_map: SourceMap,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum TokenKind {
+    /// An identifier or a number.
+    Word(String),
+    Text(String),
+    Symbol(char),
+}
+
+#[derive(Clone, Debug)]
+struct Token {
+    kind: TokenKind,
+    span: Range<usize>,
+}
+
+#[derive(Debug)]
+enum Value {
+    Struct(StructValue),
+    List(Vec<Node>),
+    Text(String),
+    /// Any other value, such as a number, an enumerant or an embedded file.
+    Other,
+}
+
+/// A value and the range of the file it was read from.
+#[derive(Debug)]
+struct Node {
+    value: Value,
+    span: Range<usize>,
+}
+
+#[derive(Debug)]
+struct Field {
+    name: String,
+    /// The start of the name of the field.
+    start: usize,
+    value: Node,
+}
+
+#[derive(Debug, Default)]
+struct StructValue {
+    fields: Vec<Field>,
+    /// The range of the struct, including its parentheses.
+    span: Range<usize>,
+}
+
+/// A value to be written into the file.
+enum Rendered {
+    Text(String),
+    List(Vec<Rendered>),
+    Struct(Vec<(&'static str, Rendered)>),
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Pkgdef {
+    /// Reads and parses the package definition file at `path`.
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let path = path.as_ref();
+        match std::fs::read_to_string(path) {
+            Ok(text) => Pkgdef::parse(&text),
+            Err(err) => Err(Error::FailedToOpenFile(
+                path.to_string_lossy().to_string(),
+                Some(err),
+            )),
+        }
+    }
+
+    /// Parses the text of a package definition file.
+    ///
+    /// The package definition is the `const` whose type is `PackageDefinition`, such as
+    /// `const pkgdef :Spk.PackageDefinition = (...);`.
+    pub fn parse(text: &str) -> Result<Self, Error> {
+        let mut parser = Parser {
+            text,
+            tokens: tokenize(text)?,
+            position: 0,
+        };
+        let definition = parser.package_definition()?;
+        let id = match definition.field(ID_FIELD) {
+            Some(node) => Some(parser.text_value(node)?),
+            None => None,
+        };
+        let always_include = match definition.field(ALWAYS_INCLUDE_FIELD) {
+            Some(node) => parser.text_list(node)?,
+            None => Vec::new(),
+        };
+        let file_list = match definition.field(FILE_LIST_FIELD) {
+            Some(node) => Some(parser.text_value(node)?),
+            None => None,
+        };
+        let source_map = match definition.field(SOURCE_MAP_FIELD) {
+            Some(node) => parser.source_map(node)?,
+            None => SourceMap::default(),
+        };
+        Ok(Self {
+            text: text.to_string(),
+            definition,
+            id,
+            parsed_always_include: always_include.clone(),
+            parsed_file_list: file_list.clone(),
+            parsed_source_map: source_map.clone(),
+            always_include,
+            file_list,
+            source_map,
+        })
+    }
+
+    /// Returns the app ID, which is the public key of the key which signs the package.
+    pub fn id(&self) -> Option<&str> {
+        self.id.as_deref()
+    }
+
+    /// Writes the package definition file at `path`.
+    ///
+    /// The text is written to a temporary file beside `path`, which is then renamed over `path`,
+    /// so that `path` is either unchanged or completely written.
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        use std::io::Write;
+
+        let path = path.as_ref();
+        let file_name = match path.file_name() {
+            Some(file_name) => file_name.to_string_lossy().to_string(),
+            None => PKGDEF_FILE_NAME.to_string(),
+        };
+        let temporary_filepath: PathBuf = path.with_file_name(format!(
+            ".{}.wheelhoss-{}.tmp",
+            file_name,
+            std::process::id()
+        ));
+        let result = File::create(&temporary_filepath)
+            .and_then(|mut file| {
+                file.write_all(self.to_string().as_bytes())?;
+                if let Ok(metadata) = std::fs::metadata(path) {
+                    file.set_permissions(metadata.permissions())?;
+                }
+                file.sync_all()
+            })
+            .and_then(|_| std::fs::rename(&temporary_filepath, path));
+        if let Err(err) = result {
+            let _ = std::fs::remove_file(&temporary_filepath);
+            return Err(Error::FailedToOpenFile(
+                temporary_filepath.to_string_lossy().to_string(),
+                Some(err),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Returns the replacements which write the changed fields into the text.
+    fn edits(&self) -> Vec<(Range<usize>, String)> {
+        let mut edits = Vec::new();
+        if self.always_include != self.parsed_always_include {
+            let rendered = text_list(&self.always_include);
+            edits.push(self.set_field(&self.definition, ALWAYS_INCLUDE_FIELD, rendered));
+        }
+        if self.file_list != self.parsed_file_list {
+            match self.file_list.as_ref() {
+                Some(file_list) => edits.push(self.set_field(
+                    &self.definition,
+                    FILE_LIST_FIELD,
+                    Rendered::Text(file_list.clone()),
+                )),
+                None => edits.extend(self.remove_field(&self.definition, FILE_LIST_FIELD)),
+            }
+        }
+        if self.source_map != self.parsed_source_map {
+            edits.extend(self.source_map_edits());
+        }
+        edits
+    }
+
+    /// Returns the replacements which write `source_map`, rewriting as little as possible.
+    fn source_map_edits(&self) -> Vec<(Range<usize>, String)> {
+        let source_map = match self.definition.field(SOURCE_MAP_FIELD) {
+            Some(Node {
+                value: Value::Struct(source_map),
+                ..
+            }) => source_map,
+            _ => {
+                return vec![self.set_field(
+                    &self.definition,
+                    SOURCE_MAP_FIELD,
+                    self.source_map.rendered(),
+                )]
+            }
+        };
+        let mappings = match source_map.field(SEARCH_PATH_FIELD) {
+            Some(Node {
+                value: Value::List(mappings),
+                ..
+            }) if mappings.len() == self.source_map.search_path.len()
+                && mappings
+                    .iter()
+                    .all(|mapping| matches!(mapping.value, Value::Struct(_))) =>
+            {
+                mappings
+            }
+            _ => {
+                return vec![self.set_field(
+                    source_map,
+                    SEARCH_PATH_FIELD,
+                    search_path(&self.source_map.search_path),
+                )]
+            }
+        };
+
+        let mut edits = Vec::new();
+        for ((node, mapping), parsed_mapping) in mappings
+            .iter()
+            .zip(self.source_map.search_path.iter())
+            .zip(self.parsed_source_map.search_path.iter())
+        {
+            let node = match &node.value {
+                Value::Struct(node) => node,
+                _ => continue,
+            };
+            if mapping.package_path != parsed_mapping.package_path {
+                let rendered = Rendered::Text(mapping.package_path.clone());
+                edits.push(self.set_field(node, PACKAGE_PATH_FIELD, rendered));
+            }
+            if mapping.source_path != parsed_mapping.source_path {
+                let rendered = Rendered::Text(mapping.source_path.clone());
+                edits.push(self.set_field(node, SOURCE_PATH_FIELD, rendered));
+            }
+            if mapping.hide_paths != parsed_mapping.hide_paths {
+                let rendered = text_list(&mapping.hide_paths);
+                edits.push(self.set_field(node, HIDE_PATHS_FIELD, rendered));
+            }
+        }
+        edits
+    }
+
+    /// Returns the replacement which sets the field `name` of `value` to `rendered`, adding the
+    /// field after the last field if it is not present.
+    fn set_field(
+        &self,
+        value: &StructValue,
+        name: &'static str,
+        rendered: Rendered,
+    ) -> (Range<usize>, String) {
+        if let Some(field) = value.fields.iter().find(|field| field.name == name) {
+            let indent = self.indent(field.start);
+            let column = field.value.span.start - self.line_start(field.value.span.start);
+            return (field.value.span.clone(), rendered.render(indent, column));
+        }
+        match value.fields.last() {
+            Some(last) if self.starts_line(last.start) => {
+                let indent = self.indent(last.start);
+                let column = indent.len() + name.len() + 3;
+                let end = last.value.span.end;
+                let field = format!(
+                    ",\n{}{} = {}",
+                    indent,
+                    name,
+                    rendered.render(indent, column)
+                );
+                (end..end, field)
+            }
+            Some(last) => {
+                let indent = self.indent(last.start);
+                let end = last.value.span.end;
+                let column = end - self.line_start(end) + name.len() + 5;
+                let field = format!(", {} = {}", name, rendered.render(indent, column));
+                (end..end, field)
+            }
+            None => {
+                let indent = self.indent(value.span.start);
+                let column = value.span.start - self.line_start(value.span.start);
+                let field = Rendered::Struct(vec![(name, rendered)]);
+                (value.span.clone(), field.render(indent, column))
+            }
+        }
+    }
+
+    /// Returns the replacement which removes the field `name` of `value`, if it is present.
+    fn remove_field(&self, value: &StructValue, name: &str) -> Option<(Range<usize>, String)> {
+        let index = value.fields.iter().position(|field| field.name == name)?;
+        let field = &value.fields[index];
+        let range = match (value.fields.get(index + 1), index.checked_sub(1)) {
+            (Some(next), _) => field.start..next.start,
+            (None, Some(previous)) => value.fields[previous].value.span.end..field.value.span.end,
+            (None, None) => field.start..field.value.span.end,
+        };
+        Some((range, String::new()))
+    }
+
+    fn line_start(&self, offset: usize) -> usize {
+        self.text[..offset].rfind('\n').map_or(0, |index| index + 1)
+    }
+
+    /// Returns the whitespace at the start of the line which holds `offset`.
+    fn indent(&self, offset: usize) -> &str {
+        let line = &self.text[self.line_start(offset)..];
+        &line[..line.len() - line.trim_start_matches([' ', '\t']).len()]
+    }
+
+    /// Returns `true` if only whitespace precedes `offset` on its line.
+    fn starts_line(&self, offset: usize) -> bool {
+        self.text[self.line_start(offset)..offset].trim().is_empty()
+    }
+}
+
+impl Display for Pkgdef {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), std::fmt::Error> {
+        let mut edits = self.edits();
+        edits.sort_by_key(|(range, _)| (range.start, range.end));
+        let mut written = 0;
+        for (range, replacement) in edits {
+            write!(f, "{}{}", &self.text[written..range.start], replacement)?;
+            written = range.end;
+        }
+        write!(f, "{}", &self.text[written..])
+    }
+}
+
+impl SourceMap {
+    fn rendered(&self) -> Rendered {
+        Rendered::Struct(vec![(SEARCH_PATH_FIELD, search_path(&self.search_path))])
+    }
+}
+
+impl Mapping {
+    /// Renders the mapping, leaving out the fields which have their default values.
+    fn rendered(&self) -> Rendered {
+        let mut fields = Vec::new();
+        if !self.package_path.is_empty() {
+            fields.push((
+                PACKAGE_PATH_FIELD,
+                Rendered::Text(self.package_path.clone()),
+            ));
+        }
+        fields.push((SOURCE_PATH_FIELD, Rendered::Text(self.source_path.clone())));
+        if !self.hide_paths.is_empty() {
+            fields.push((HIDE_PATHS_FIELD, text_list(&self.hide_paths)));
+        }
+        Rendered::Struct(fields)
+    }
+}
+
+impl StructValue {
+    /// Returns the value of the last field named `name`.
+    fn field(&self, name: &str) -> Option<&Node> {
+        self.fields
+            .iter()
+            .rev()
+            .find(|field| field.name == name)
+            .map(|field| &field.value)
+    }
+}
+
+impl Rendered {
+    /// Renders the value on one line if it fits after `column`, or over several lines indented
+    /// from `indent` otherwise.
+    fn render(&self, indent: &str, column: usize) -> String {
+        let line = self.render_line();
+        if column + line.len() <= MAX_LINE_LENGTH {
+            return line;
+        }
+        let inner = format!("{}{}", indent, INDENT);
+        match self {
+            Self::Text(_) => line,
+            Self::List(items) => {
+                let items: Vec<String> = items
+                    .iter()
+                    .map(|item| format!("{}{}", inner, item.render(&inner, inner.len())))
+                    .collect();
+                format!("[\n{}\n{}]", items.join(",\n"), indent)
+            }
+            Self::Struct(fields) => {
+                let fields: Vec<String> = fields
+                    .iter()
+                    .map(|(name, value)| {
+                        let column = inner.len() + name.len() + 3;
+                        format!("{}{} = {}", inner, name, value.render(&inner, column))
+                    })
+                    .collect();
+                format!("(\n{}\n{})", fields.join(",\n"), indent)
+            }
+        }
+    }
+
+    fn render_line(&self) -> String {
+        match self {
+            Self::Text(text) => quote(text),
+            Self::List(items) if items.is_empty() => "[]".to_string(),
+            Self::List(items) => {
+                let items: Vec<String> = items.iter().map(Rendered::render_line).collect();
+                format!("[ {} ]", items.join(", "))
+            }
+            Self::Struct(fields) if fields.is_empty() => "()".to_string(),
+            Self::Struct(fields) => {
+                let fields: Vec<String> = fields
+                    .iter()
+                    .map(|(name, value)| format!("{} = {}", name, value.render_line()))
+                    .collect();
+                format!("( {} )", fields.join(", "))
+            }
+        }
+    }
+}
+
+impl<'a> Parser<'a> {
+    /// Finds the package definition constant and parses its value.
+    fn package_definition(&mut self) -> Result<StructValue, Error> {
+        while let Some(token) = self.next() {
+            if token.kind != TokenKind::Word("const".to_string()) {
+                self.skip_statement(&token)?;
+                continue;
+            }
+            self.word()?;
+            self.expect(':')?;
+            let mut type_name = String::new();
+            loop {
+                let token = self.required()?;
+                match token.kind {
+                    TokenKind::Symbol('=') => break,
+                    TokenKind::Word(word) => type_name = word,
+                    TokenKind::Symbol('.') => {}
+                    _ => return Err(self.error(&token, "expected a type")),
+                }
+            }
+            let value = self.value()?;
+            self.expect(';')?;
+            if type_name == PACKAGE_DEFINITION_TYPE {
+                return match value.value {
+                    Value::Struct(definition) => Ok(definition),
+                    _ => Err(Error::InvalidPkgdef(format!(
+                        "line {}: expected a struct",
+                        self.line(value.span.start)
+                    ))),
+                };
+            }
+        }
+        Err(Error::InvalidPkgdef(format!(
+            "no constant of type {}",
+            PACKAGE_DEFINITION_TYPE
+        )))
+    }
+
+    /// Skips the rest of the statement which starts with `token`, such as `using` or `@0x...;`.
+    fn skip_statement(&mut self, token: &Token) -> Result<(), Error> {
+        let mut depth = 0;
+        let mut current = token.clone();
+        loop {
+            match current.kind {
+                TokenKind::Symbol('(') | TokenKind::Symbol('[') | TokenKind::Symbol('{') => {
+                    depth += 1
+                }
+                TokenKind::Symbol(')') | TokenKind::Symbol(']') => depth -= 1,
+                TokenKind::Symbol('}') => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Ok(());
+                    }
+                }
+                TokenKind::Symbol(';') if depth == 0 => return Ok(()),
+                _ => {}
+            }
+            current = self.required()?;
+        }
+    }
+
+    fn value(&mut self) -> Result<Node, Error> {
+        let token = self.required()?;
+        match token.kind {
+            TokenKind::Symbol('(') => {
+                let value = self.struct_value(token.span.start)?;
+                let span = value.span.clone();
+                Ok(Node {
+                    value: Value::Struct(value),
+                    span,
+                })
+            }
+            TokenKind::Symbol('[') => {
+                let mut items = Vec::new();
+                let end = loop {
+                    if let Some(end) = self.accept(']') {
+                        break end;
+                    }
+                    items.push(self.value()?);
+                    if self.accept(',').is_none() {
+                        break self.expect(']')?;
+                    }
+                };
+                Ok(Node {
+                    value: Value::List(items),
+                    span: token.span.start..end,
+                })
+            }
+            TokenKind::Text(text) => Ok(Node {
+                value: Value::Text(text),
+                span: token.span,
+            }),
+            TokenKind::Symbol(symbol) if ",;)]=".contains(symbol) => {
+                Err(self.error(&token, "expected a value"))
+            }
+            _ => {
+                let mut end = token.span.end;
+                while let Some(next) = self.peek() {
+                    match next.kind {
+                        TokenKind::Symbol(symbol) if ",;)]".contains(symbol) => break,
+                        TokenKind::Symbol('(') | TokenKind::Symbol('[') => {
+                            end = self.value()?.span.end;
+                        }
+                        _ => {
+                            end = next.span.end;
+                            self.position += 1;
+                        }
+                    }
+                }
+                Ok(Node {
+                    value: Value::Other,
+                    span: token.span.start..end,
+                })
+            }
+        }
+    }
+
+    /// Parses the fields of a struct whose `(` is at `start`.
+    fn struct_value(&mut self, start: usize) -> Result<StructValue, Error> {
+        let mut fields = Vec::new();
+        let end = loop {
+            if let Some(end) = self.accept(')') {
+                break end;
+            }
+            let (name, name_start) = self.word()?;
+            self.expect('=')?;
+            let value = self.value()?;
+            fields.push(Field {
+                name,
+                start: name_start,
+                value,
+            });
+            if self.accept(',').is_none() {
+                break self.expect(')')?;
+            }
+        };
+        Ok(StructValue {
+            fields,
+            span: start..end,
+        })
+    }
+
+    fn source_map(&self, node: &Node) -> Result<SourceMap, Error> {
+        let source_map = self.struct_of(node)?;
+        let mut search_path = Vec::new();
+        if let Some(node) = source_map.field(SEARCH_PATH_FIELD) {
+            for node in self.list_of(node)? {
+                let mapping = self.struct_of(node)?;
+                search_path.push(Mapping {
+                    package_path: match mapping.field(PACKAGE_PATH_FIELD) {
+                        Some(node) => self.text_value(node)?,
+                        None => String::new(),
+                    },
+                    source_path: match mapping.field(SOURCE_PATH_FIELD) {
+                        Some(node) => self.text_value(node)?,
+                        None => String::new(),
+                    },
+                    hide_paths: match mapping.field(HIDE_PATHS_FIELD) {
+                        Some(node) => self.text_list(node)?,
+                        None => Vec::new(),
+                    },
+                });
+            }
+        }
+        Ok(SourceMap { search_path })
+    }
+
+    fn struct_of<'n>(&self, node: &'n Node) -> Result<&'n StructValue, Error> {
+        match &node.value {
+            Value::Struct(value) => Ok(value),
+            _ => Err(self.expected(node, "a struct")),
+        }
+    }
+
+    fn list_of<'n>(&self, node: &'n Node) -> Result<&'n [Node], Error> {
+        match &node.value {
+            Value::List(items) => Ok(items),
+            _ => Err(self.expected(node, "a list")),
+        }
+    }
+
+    fn text_value(&self, node: &Node) -> Result<String, Error> {
+        match &node.value {
+            Value::Text(text) => Ok(text.clone()),
+            _ => Err(self.expected(node, "a string")),
+        }
+    }
+
+    fn text_list(&self, node: &Node) -> Result<Vec<String>, Error> {
+        self.list_of(node)?
+            .iter()
+            .map(|item| self.text_value(item))
+            .collect()
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn peek(&self) -> Option<Token> {
+        self.tokens.get(self.position).cloned()
+    }
+
+    fn required(&mut self) -> Result<Token, Error> {
+        self.next()
+            .ok_or_else(|| Error::InvalidPkgdef("unexpected end of file".to_string()))
+    }
+
+    /// Consumes the symbol `symbol` if it is next, returning the offset after it.
+    fn accept(&mut self, symbol: char) -> Option<usize> {
+        match self.peek() {
+            Some(token) if token.kind == TokenKind::Symbol(symbol) => {
+                self.position += 1;
+                Some(token.span.end)
+            }
+            _ => None,
+        }
+    }
+
+    fn expect(&mut self, symbol: char) -> Result<usize, Error> {
+        let token = self.required()?;
+        if token.kind == TokenKind::Symbol(symbol) {
+            Ok(token.span.end)
+        } else {
+            Err(self.error(&token, &format!("expected \"{}\"", symbol)))
+        }
+    }
+
+    fn word(&mut self) -> Result<(String, usize), Error> {
+        let token = self.required()?;
+        match token.kind {
+            TokenKind::Word(word) => Ok((word, token.span.start)),
+            _ => Err(self.error(&token, "expected a name")),
+        }
+    }
+
+    fn line(&self, offset: usize) -> usize {
+        self.text[..offset].matches('\n').count() + 1
+    }
+
+    fn error(&self, token: &Token, message: &str) -> Error {
+        Error::InvalidPkgdef(format!(
+            "line {}: {}, found \"{}\"",
+            self.line(token.span.start),
+            message,
+            &self.text[token.span.clone()]
+        ))
+    }
+
+    fn expected(&self, node: &Node, expected: &str) -> Error {
+        Error::InvalidPkgdef(format!(
+            "line {}: expected {}",
+            self.line(node.span.start),
+            expected
+        ))
+    }
+}
+
+fn text_list(items: &[String]) -> Rendered {
+    Rendered::List(items.iter().cloned().map(Rendered::Text).collect())
+}
+
+fn search_path(mappings: &[Mapping]) -> Rendered {
+    Rendered::List(mappings.iter().map(Mapping::rendered).collect())
+}
+
+/// Returns `text` as a Cap'n Proto string literal.
+fn quote(text: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => quoted.push_str(&format!("\\x{:02x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Splits the text of a Cap'n Proto file into tokens, leaving out whitespace and comments.
+fn tokenize(text: &str) -> Result<Vec<Token>, Error> {
+    let mut tokens = Vec::new();
+    let mut chars = text.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        let kind = match c {
+            c if c.is_whitespace() => continue,
+            '#' => {
+                while chars.next_if(|(_, c)| *c != '\n').is_some() {}
+                continue;
+            }
+            '"' => {
+                let mut string = String::new();
+                loop {
+                    match chars.next() {
+                        Some((_, '"')) => break,
+                        Some((offset, '\\')) => match chars.next() {
+                            Some((_, 'n')) => string.push('\n'),
+                            Some((_, 't')) => string.push('\t'),
+                            Some((_, 'r')) => string.push('\r'),
+                            Some((_, '0')) => string.push('\0'),
+                            Some((_, 'x')) => {
+                                let mut value = 0;
+                                for _ in 0..2 {
+                                    match chars.next().and_then(|(_, c)| c.to_digit(16)) {
+                                        Some(digit) => value = value * 16 + digit,
+                                        None => return Err(escape_error(text, offset)),
+                                    }
+                                }
+                                string.push(char::from(value as u8));
+                            }
+                            Some((_, c)) if "\"'\\?".contains(c) => string.push(c),
+                            _ => return Err(escape_error(text, offset)),
+                        },
+                        Some((_, c)) => string.push(c),
+                        None => {
+                            return Err(Error::InvalidPkgdef(format!(
+                                "line {}: unterminated string",
+                                text[..start].matches('\n').count() + 1
+                            )))
+                        }
+                    }
+                }
+                TokenKind::Text(string)
+            }
+            c if c.is_alphanumeric() || c == '_' => {
+                let mut word = c.to_string();
+                while let Some((_, c)) = chars.next_if(|(_, c)| c.is_alphanumeric() || *c == '_') {
+                    word.push(c);
+                }
+                TokenKind::Word(word)
+            }
+            c => TokenKind::Symbol(c),
+        };
+        let end = chars.peek().map_or(text.len(), |(offset, _)| *offset);
+        tokens.push(Token {
+            kind,
+            span: start..end,
+        });
+    }
+    Ok(tokens)
+}
+
+fn escape_error(text: &str, offset: usize) -> Error {
+    Error::InvalidPkgdef(format!(
+        "line {}: unsupported escape sequence",
+        text[..offset].matches('\n').count() + 1
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    const PKGDEF: &str = r#"@0xb1d5b5e4f3b6a1c2;
+
+using Spk = import "/sandstorm/package.capnp";
+# This manifest is read by `spk`.
+
+const pkgdef :Spk.PackageDefinition = (
+  id = "vyvdr9c3x8dxw9yx6zvz6eh95cutq7hsnzj5pk1h3p0e1kmfsfm0",
+
+  manifest = (
+    appTitle = (defaultText = "Django App"),
+    appVersion = 3,  # Increment this for every release.
+    actions = [
+      ( nounPhrase = (defaultText = "instance"),
+        command = .myCommand
+      )
+    ],
+    metadata = (icons = (appGrid = (svg = embed "app-graphics/icon.svg"))),
+  ),
+
+  sourceMap = (
+    searchPath = [
+      ( sourcePath = "." ),  # Search this directory first.
+      ( sourcePath = "/",    # Then search the system root directory.
+        hidePaths = [ "home", "proc", "sys",
+                      "etc/passwd", "etc/hosts" ]
+      )
+    ]
+  ),
+
+  fileList = "sandstorm-files.list",
+  # Files which are always included.
+  alwaysInclude = [ "opt/app/static" ],
+);
+
+const myCommand :Spk.Manifest.Command = (
+  argv = ["/sandstorm-http-bridge", "8000", "--", "/bin/bash", "/opt/app/.sandstorm/launcher.sh"],
+);
+"#;
+
+    #[test]
+    fn pkgdef_parse() {
+        let pkgdef = Pkgdef::parse(PKGDEF).unwrap();
+        assert_eq!(
+            Some("vyvdr9c3x8dxw9yx6zvz6eh95cutq7hsnzj5pk1h3p0e1kmfsfm0"),
+            pkgdef.id()
+        );
+        assert_eq!(vec!["opt/app/static"], pkgdef.always_include);
+        assert_eq!(Some("sandstorm-files.list"), pkgdef.file_list.as_deref());
+        assert_eq!(
+            SourceMap {
+                search_path: vec![
+                    Mapping {
+                        package_path: "".to_string(),
+                        source_path: ".".to_string(),
+                        hide_paths: vec![],
+                    },
+                    Mapping {
+                        package_path: "".to_string(),
+                        source_path: "/".to_string(),
+                        hide_paths: ["home", "proc", "sys", "etc/passwd", "etc/hosts"]
+                            .iter()
+                            .map(|path| path.to_string())
+                            .collect(),
+                    },
+                ]
+            },
+            pkgdef.source_map
+        );
+        assert_eq!(PKGDEF, pkgdef.to_string());
+    }
+
+    #[test]
+    fn pkgdef_parse_rejects_other_files() {
+        assert!(matches!(
+            Pkgdef::parse("const x :Text = \"x\";"),
+            Err(Error::InvalidPkgdef(_))
+        ));
+        assert!(matches!(
+            Pkgdef::parse("const pkgdef :Spk.PackageDefinition = (alwaysInclude = \"x\");"),
+            Err(Error::InvalidPkgdef(_))
+        ));
+        assert!(matches!(
+            Pkgdef::parse("const pkgdef :Spk.PackageDefinition = (id = \"x"),
+            Err(Error::InvalidPkgdef(_))
+        ));
+    }
+
+    #[test]
+    fn pkgdef_edit() {
+        let mut pkgdef = Pkgdef::parse(PKGDEF).unwrap();
+        pkgdef.always_include.push("opt/app/locale".to_string());
+        pkgdef.source_map.search_path[1]
+            .hide_paths
+            .push("var/run".to_string());
+        pkgdef.source_map.search_path[0].package_path = "opt/app".to_string();
+        let expected = PKGDEF
+            .replace(
+                "( sourcePath = \".\" )",
+                "( sourcePath = \".\", packagePath = \"opt/app\" )",
+            )
+            .replace(
+                "[ \"home\", \"proc\", \"sys\",\n                      \"etc/passwd\", \"etc/hosts\" ]",
+                "[ \"home\", \"proc\", \"sys\", \"etc/passwd\", \"etc/hosts\", \"var/run\" ]",
+            )
+            .replace(
+                "[ \"opt/app/static\" ]",
+                "[ \"opt/app/static\", \"opt/app/locale\" ]",
+            );
+        assert_eq!(expected, pkgdef.to_string());
+
+        let reparsed = Pkgdef::parse(&pkgdef.to_string()).unwrap();
+        assert_eq!(pkgdef.always_include, reparsed.always_include);
+        assert_eq!(pkgdef.source_map, reparsed.source_map);
+    }
+
+    #[test]
+    fn pkgdef_edit_adds_and_removes_fields() {
+        let text = "const pkgdef :Spk.PackageDefinition = (\n  id = \"x\",\n  fileList = \"files.list\"\n);\n";
+        let mut pkgdef = Pkgdef::parse(text).unwrap();
+        pkgdef.file_list = None;
+        pkgdef.always_include = vec!["opt/app".to_string()];
+        pkgdef.source_map.search_path.push(Mapping {
+            package_path: String::new(),
+            source_path: ".".to_string(),
+            hide_paths: Vec::new(),
+        });
+        assert_eq!(
+            "const pkgdef :Spk.PackageDefinition = (\n  id = \"x\",\n  \
+             alwaysInclude = [ \"opt/app\" ],\n  \
+             sourceMap = ( searchPath = [ ( sourcePath = \".\" ) ] )\n);\n",
+            pkgdef.to_string()
+        );
+
+        let mut pkgdef = Pkgdef::parse("const pkgdef :PackageDefinition = ();").unwrap();
+        pkgdef.always_include = (0..10)
+            .map(|n| format!("opt/app/directory-{}", n))
+            .collect();
+        let written = pkgdef.to_string();
+        assert!(written.starts_with(
+            "const pkgdef :PackageDefinition = (\n  alwaysInclude = [\n    \"opt/app/directory-0\",\n"
+        ));
+        assert_eq!(
+            pkgdef.always_include,
+            Pkgdef::parse(&written).unwrap().always_include
+        );
+    }
+}
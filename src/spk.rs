@@ -0,0 +1,709 @@
+//! Sandstorm packages
+//!
+//! `spk` writes `.spk` files, the signed packages which Sandstorm installs, as `spk pack` does.
+//! A package is the magic number followed by an xz stream holding two
+//! [Cap'n Proto](https://capnproto.org/encoding.html) messages: a `Signature` and the `Archive` of
+//! the files of the package.  The signature is the Ed25519 signature of the Blake2b-512 hash of
+//! the archive, made with the private key of the app, whose public key is the app ID.
+
+use std::collections::{btree_map, BTreeMap};
+use std::convert::TryFrom;
+use std::fs::{File, Metadata};
+use std::io::{ErrorKind, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+use blake2::{Blake2b512, Digest};
+use ed25519_dalek::{Signer, SigningKey};
+use xz2::write::XzEncoder;
+
+use crate::error::Error;
+use crate::files_list::PackageContents;
+
+/// The bytes which start every `.spk` file.
+pub const MAGIC_NUMBER: [u8; 8] = [0x8f, 0xc6, 0xcd, 0xef, 0x45, 0x1a, 0xea, 0x96];
+/// The name of the keyring file of `spk` in the home directory.
+pub const KEYRING_FILE_NAME: &str = ".sandstorm-keyring";
+/// The path in the package of the manifest, which Sandstorm reads to install the app.
+pub const MANIFEST_PATH: &str = "/sandstorm-manifest";
+
+/// The xz preset of the compressed archive, which is the default of `xz`.
+const XZ_PRESET: u32 = 6;
+/// The digits of the base32 encoding of app IDs.
+const BASE32_DIGITS: &[u8; 32] = b"0123456789acdefghjkmnpqrstuvwxyz";
+/// Byte lists, such as the contents of a file, hold fewer elements than this.
+const MAX_LIST_ELEMENTS: usize = 1 << 29;
+
+// The discriminants of the union of `Archive.File`.
+const REGULAR: u64 = 0;
+const EXECUTABLE: u64 = 1;
+const SYMLINK: u64 = 2;
+const DIRECTORY: u64 = 3;
+
+/// A file in the tree of the archive.
+enum Entry<'a> {
+    File(&'a Path),
+    Symlink(&'a str),
+    Directory(BTreeMap<&'a str, Entry<'a>>),
+}
+
+/// Builds a single segment Cap'n Proto message.  Positions are in words from the start of the
+/// segment, whose first word is the root pointer.
+struct MessageBuilder {
+    segment: Vec<u8>,
+}
+
+/// A struct in a single segment Cap'n Proto message.
+struct StructReader<'a> {
+    segment: &'a [u8],
+    data: usize,
+    data_words: usize,
+    pointers: usize,
+    pointer_count: usize,
+}
+
+/// Returns the app ID of the app signed with `signing_key`, which is the base32 encoding of its
+/// public key, such as `vyvdr9c3x8dxw9yx6zvz6eh95cutq7hsnzj5pk1h3p0e1kmfsfm0`.
+pub fn app_id(signing_key: &SigningKey) -> String {
+    base32(signing_key.verifying_key().as_bytes())
+}
+
+/// Returns the Cap'n Proto message of the `Archive` of `contents`.
+///
+/// Regular files are read from their paths on the host and are executable if the owner may
+/// execute them, as `spk pack` decides.  Files keep their modification times; directories and
+/// symbolic links have none, as their paths on the host are not known.  A path which is both a
+/// directory and a file or symbolic link, or both a file and a symbolic link, is an error.
+pub fn archive(contents: &PackageContents) -> Result<Vec<u8>, Error> {
+    let mut root: BTreeMap<&str, Entry> = BTreeMap::new();
+    for directory in contents.directories.iter() {
+        directory_of(&mut root, directory)?;
+    }
+    for (path, host_path) in contents.files.iter() {
+        insert_entry(&mut root, path, Entry::File(host_path))?;
+    }
+    for (path, target) in contents.symlinks.iter() {
+        insert_entry(&mut root, path, Entry::Symlink(target))?;
+    }
+
+    let mut message = MessageBuilder::new();
+    let archive = message.init_struct(0, 0, 1);
+    message.set_files(archive, &root)?;
+    message.into_message()
+}
+
+/// Reads the signing keys in the keyring file at `path`, which `spk keygen` writes.
+pub fn read_keyring<P: AsRef<Path>>(path: P) -> Result<Vec<SigningKey>, Error> {
+    let path = path.as_ref();
+    let name = path.to_string_lossy().to_string();
+    let bytes =
+        std::fs::read(path).map_err(|err| Error::FailedToOpenFile(name.clone(), Some(err)))?;
+    let invalid = |message: &str| Error::InvalidKeyring(name.clone(), message.to_string());
+
+    let mut signing_keys = Vec::new();
+    let mut rest = bytes.as_slice();
+    while !rest.is_empty() {
+        let (segment, next) = first_segment(rest).ok_or_else(|| invalid("truncated message"))?;
+        rest = next;
+        let key_file = StructReader::at(segment, 0).ok_or_else(|| invalid("invalid key file"))?;
+        let public_key = key_file
+            .pointer(0)
+            .ok_or_else(|| invalid("invalid public key"))?
+            .data_bytes(4);
+        let private_key = key_file
+            .pointer(1)
+            .ok_or_else(|| invalid("invalid private key"))?
+            .data_bytes(8);
+        let mut seed = [0; 32];
+        seed.copy_from_slice(&private_key[..32]);
+        let signing_key = SigningKey::from_bytes(&seed);
+        if signing_key.verifying_key().as_bytes()[..] != public_key[..] {
+            return Err(invalid("a private key does not match its public key"));
+        }
+        signing_keys.push(signing_key);
+    }
+    Ok(signing_keys)
+}
+
+/// Returns the signing key of the app `app_id` in the keyring file at `path`.
+pub fn signing_key<P: AsRef<Path>>(path: P, app_id: &str) -> Result<SigningKey, Error> {
+    read_keyring(path)?
+        .into_iter()
+        .find(|signing_key| self::app_id(signing_key) == app_id)
+        .ok_or_else(|| Error::NoSigningKey(app_id.to_string()))
+}
+
+/// Writes the package of `archive`, a message returned by `archive`, signed with `signing_key`
+/// to `writer`, which is returned.
+pub fn write_spk<W: Write>(
+    mut writer: W,
+    archive: &[u8],
+    signing_key: &SigningKey,
+) -> Result<W, Error> {
+    let hash = Blake2b512::digest(archive);
+    // The signature is in the format of `crypto_sign` of libsodium: the signature of the hash
+    // followed by the hash.
+    let mut signature = signing_key.sign(&hash).to_bytes().to_vec();
+    signature.extend_from_slice(&hash);
+
+    let mut message = MessageBuilder::new();
+    let root = message.init_struct(0, 0, 2);
+    message.set_bytes(root, signing_key.verifying_key().as_bytes())?;
+    message.set_bytes(root + 1, &signature)?;
+
+    writer.write_all(&MAGIC_NUMBER)?;
+    let mut encoder = XzEncoder::new(writer, XZ_PRESET);
+    encoder.write_all(&message.into_message()?)?;
+    encoder.write_all(archive)?;
+    Ok(encoder.finish()?)
+}
+
+/// Writes the package of `archive` signed with `signing_key` to the file at `path`, as
+/// `write_spk` does.
+///
+/// The package is written to a temporary file beside `path`, which is synchronized to disk and
+/// then renamed to `path`.  If `create_spk` returns an `Error`, a file at `path` is unchanged.
+pub fn create_spk<P: AsRef<Path>>(
+    path: P,
+    archive: &[u8],
+    signing_key: &SigningKey,
+) -> Result<(), Error> {
+    let path = path.as_ref();
+    let file_name = match path.file_name() {
+        Some(file_name) => file_name.to_string_lossy().to_string(),
+        None => {
+            return Err(Error::FailedToOpenFile(
+                path.to_string_lossy().to_string(),
+                None,
+            ))
+        }
+    };
+    let temporary_path = path.with_file_name(format!(
+        ".{}.wheelhoss-{}.tmp",
+        file_name,
+        std::process::id()
+    ));
+    let result = File::create(&temporary_path)
+        .map_err(|err| {
+            Error::FailedToOpenFile(temporary_path.to_string_lossy().to_string(), Some(err))
+        })
+        .and_then(|file| Ok(write_spk(file, archive, signing_key)?.sync_all()?))
+        .and_then(|_| {
+            std::fs::rename(&temporary_path, path).map_err(|err| {
+                Error::FailedToOpenFile(path.to_string_lossy().to_string(), Some(err))
+            })
+        });
+    if result.is_err() {
+        let _ = std::fs::remove_file(&temporary_path);
+    }
+    result
+}
+
+/// Adds `entry` at `path`, a package path with a leading `/`, to the tree `root`, or fails if
+/// there already is an entry at `path`.
+fn insert_entry<'a>(
+    root: &mut BTreeMap<&'a str, Entry<'a>>,
+    path: &'a str,
+    entry: Entry<'a>,
+) -> Result<(), Error> {
+    let (directory, name) = path.rsplit_once('/').unwrap_or(("", path));
+    match directory_of(root, directory)?.entry(name) {
+        btree_map::Entry::Vacant(vacant) => {
+            vacant.insert(entry);
+            Ok(())
+        }
+        btree_map::Entry::Occupied(_) => Err(conflicting_entries(path)),
+    }
+}
+
+/// Returns the directory at `path`, a package path with a leading `/`, in the tree `root`,
+/// creating it and its parents as needed, or fails if one of them is a file or symbolic link.
+fn directory_of<'t, 'a>(
+    root: &'t mut BTreeMap<&'a str, Entry<'a>>,
+    path: &'a str,
+) -> Result<&'t mut BTreeMap<&'a str, Entry<'a>>, Error> {
+    let mut directory = root;
+    let mut end = 0;
+    for name in path.split('/') {
+        end += name.len() + 1;
+        if name.is_empty() {
+            continue;
+        }
+        let entry = directory
+            .entry(name)
+            .or_insert_with(|| Entry::Directory(BTreeMap::new()));
+        directory = match entry {
+            Entry::Directory(children) => children,
+            _ => return Err(conflicting_entries(&path[..end - 1])),
+        };
+    }
+    Ok(directory)
+}
+
+/// Returns the error for a path which holds more than one entry of the package.
+fn conflicting_entries(path: &str) -> Error {
+    Error::IoError(std::io::Error::new(
+        ErrorKind::InvalidData,
+        format!("more than one entry in the package at {}", path),
+    ))
+}
+
+/// Returns the base32 encoding of `bytes` used by Sandstorm, which takes the bits most
+/// significant first and has no padding.
+fn base32(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity((bytes.len() * 8).div_ceil(5));
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for byte in bytes {
+        buffer = buffer << 8 | u32::from(*byte);
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            encoded.push(BASE32_DIGITS[(buffer >> bits & 31) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        encoded.push(BASE32_DIGITS[(buffer << (5 - bits) & 31) as usize] as char);
+    }
+    encoded
+}
+
+/// Returns the modification time of `metadata` in nanoseconds since the Unix epoch.
+fn modification_time_ns(metadata: &Metadata) -> i64 {
+    match metadata.modified() {
+        Ok(modified) => match modified.duration_since(UNIX_EPOCH) {
+            Ok(duration) => duration.as_nanos() as i64,
+            Err(err) => -(err.duration().as_nanos() as i64),
+        },
+        Err(_) => 0,
+    }
+}
+
+/// Returns the first segment of the Cap'n Proto message at the start of `bytes` and the bytes
+/// after the message, or `None` if the message is truncated.
+fn first_segment(bytes: &[u8]) -> Option<(&[u8], &[u8])> {
+    let u32_at = |offset: usize| -> Option<usize> {
+        let word = bytes.get(offset..offset + 4)?;
+        Some(u32::from_le_bytes([word[0], word[1], word[2], word[3]]) as usize)
+    };
+    let segment_count = u32_at(0)? + 1;
+    let header_length = (4 + 4 * segment_count).div_ceil(8) * 8;
+    let mut message_length = header_length;
+    for index in 0..segment_count {
+        message_length += u32_at(4 + 4 * index)? * 8;
+    }
+    if bytes.len() < message_length {
+        return None;
+    }
+    let first_length = u32_at(4)? * 8;
+    Some((
+        &bytes[header_length..header_length + first_length],
+        &bytes[message_length..],
+    ))
+}
+
+impl MessageBuilder {
+    fn new() -> Self {
+        Self {
+            segment: vec![0; 8],
+        }
+    }
+
+    /// Appends `words` zeroed words to the segment and returns the position of the first.
+    fn allocate(&mut self, words: usize) -> usize {
+        let position = self.segment.len() / 8;
+        self.segment.resize(self.segment.len() + words * 8, 0);
+        position
+    }
+
+    fn set_word(&mut self, position: usize, value: u64) {
+        self.segment[position * 8..position * 8 + 8].copy_from_slice(&value.to_le_bytes());
+    }
+
+    /// Sets the pointer at `position` to `target`, with the pointer `kind` in its lowest bits and
+    /// `upper` in its upper 32 bits.
+    fn set_pointer(&mut self, position: usize, target: usize, kind: u64, upper: u64) {
+        let offset = (target as i64 - position as i64 - 1) as u32;
+        self.set_word(position, u64::from(offset << 2) | kind | upper << 32);
+    }
+
+    /// Allocates a struct for the pointer at `position` and returns the position of its data.
+    /// Its pointers follow its `data_words` words of data.
+    fn init_struct(&mut self, position: usize, data_words: usize, pointers: usize) -> usize {
+        let target = self.allocate(data_words + pointers);
+        self.set_pointer(
+            position,
+            target,
+            0,
+            data_words as u64 | (pointers as u64) << 16,
+        );
+        target
+    }
+
+    /// Allocates a list of `count` structs for the pointer at `position` and returns the
+    /// position of the first struct.
+    fn init_struct_list(
+        &mut self,
+        position: usize,
+        count: usize,
+        data_words: usize,
+        pointers: usize,
+    ) -> usize {
+        let words = count * (data_words + pointers);
+        let tag = self.allocate(1 + words);
+        // Element size 7 is a list of structs, which starts with a tag word shaped like a struct
+        // pointer, whose offset is the number of elements.
+        self.set_pointer(position, tag, 1, 7 | (words as u64) << 3);
+        self.set_word(
+            tag,
+            (count as u64) << 2 | (data_words as u64) << 32 | (pointers as u64) << 48,
+        );
+        tag + 1
+    }
+
+    /// Sets the pointer at `position` to a `Data` holding `bytes`.
+    fn set_bytes(&mut self, position: usize, bytes: &[u8]) -> Result<(), Error> {
+        if bytes.len() >= MAX_LIST_ELEMENTS {
+            return Err(Error::IoError(std::io::Error::new(
+                ErrorKind::InvalidData,
+                format!("{} bytes are too many for a package", bytes.len()),
+            )));
+        }
+        let target = self.allocate(bytes.len().div_ceil(8));
+        self.segment[target * 8..target * 8 + bytes.len()].copy_from_slice(bytes);
+        // Element size 2 is a list of bytes.
+        self.set_pointer(position, target, 1, 2 | (bytes.len() as u64) << 3);
+        Ok(())
+    }
+
+    /// Sets the pointer at `position` to a `Text` holding `text`, which ends with a NUL byte.
+    fn set_text(&mut self, position: usize, text: &str) -> Result<(), Error> {
+        let mut bytes = Vec::with_capacity(text.len() + 1);
+        bytes.extend_from_slice(text.as_bytes());
+        bytes.push(0);
+        self.set_bytes(position, &bytes)
+    }
+
+    /// Sets the pointer at `position` to a `List(Archive.File)` of the entries of `directory`.
+    fn set_files(
+        &mut self,
+        position: usize,
+        directory: &BTreeMap<&str, Entry>,
+    ) -> Result<(), Error> {
+        // `Archive.File` has two words of data, the union discriminant and
+        // `lastModificationTimeNs`, and two pointers, `name` and the union.
+        let first = self.init_struct_list(position, directory.len(), 2, 2);
+        for (index, (name, entry)) in directory.iter().enumerate() {
+            let file = first + index * 4;
+            self.set_text(file + 2, name)?;
+            match entry {
+                Entry::File(host_path) => {
+                    let metadata = host_path.metadata()?;
+                    if !metadata.is_file() {
+                        return Err(Error::IoError(std::io::Error::new(
+                            ErrorKind::InvalidData,
+                            format!("not a regular file: {}", host_path.to_string_lossy()),
+                        )));
+                    }
+                    let is_executable = metadata.permissions().mode() & 0o100 != 0;
+                    let bytes = std::fs::read(host_path)?;
+                    self.set_bytes(file + 3, &bytes)?;
+                    self.set_word(file, if is_executable { EXECUTABLE } else { REGULAR });
+                    self.set_word(file + 1, modification_time_ns(&metadata) as u64);
+                }
+                Entry::Symlink(target) => {
+                    self.set_text(file + 3, target)?;
+                    self.set_word(file, SYMLINK);
+                }
+                Entry::Directory(children) => {
+                    self.set_files(file + 3, children)?;
+                    self.set_word(file, DIRECTORY);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the message: the segment table followed by the segment.
+    fn into_message(self) -> Result<Vec<u8>, Error> {
+        let words = u32::try_from(self.segment.len() / 8).map_err(|_| {
+            Error::IoError(std::io::Error::new(
+                ErrorKind::InvalidData,
+                "the archive is too large for a package",
+            ))
+        })?;
+        let mut message = Vec::with_capacity(8 + self.segment.len());
+        message.extend_from_slice(&0u32.to_le_bytes());
+        message.extend_from_slice(&words.to_le_bytes());
+        message.extend_from_slice(&self.segment);
+        Ok(message)
+    }
+}
+
+impl<'a> StructReader<'a> {
+    /// Returns the struct which the struct pointer at `position` of `segment` points to, or
+    /// `None` if it is not a struct pointer or points outside of `segment`.
+    fn at(segment: &'a [u8], position: usize) -> Option<Self> {
+        let word = word_at(segment, position)?;
+        if word & 3 != 0 {
+            return None;
+        }
+        let offset = (word as u32 as i32) >> 2;
+        let data = usize::try_from(position as i64 + 1 + i64::from(offset)).ok()?;
+        let data_words = (word >> 32 & 0xffff) as usize;
+        let pointer_count = (word >> 48) as usize;
+        if (data + data_words + pointer_count) * 8 > segment.len() {
+            return None;
+        }
+        Some(Self {
+            segment,
+            data,
+            data_words,
+            pointers: data + data_words,
+            pointer_count,
+        })
+    }
+
+    /// Returns the struct which pointer `index` points to.
+    fn pointer(&self, index: usize) -> Option<Self> {
+        if index >= self.pointer_count {
+            return None;
+        }
+        Self::at(self.segment, self.pointers + index)
+    }
+
+    /// Returns the first `words` words of data, with the words the struct does not have zeroed.
+    fn data_bytes(&self, words: usize) -> Vec<u8> {
+        let mut bytes = vec![0; words * 8];
+        let available = words.min(self.data_words) * 8;
+        bytes[..available].copy_from_slice(&self.segment[self.data * 8..self.data * 8 + available]);
+        bytes
+    }
+}
+
+/// Returns the word at `position` of `segment`.
+fn word_at(segment: &[u8], position: usize) -> Option<u64> {
+    let bytes = segment.get(position * 8..position * 8 + 8)?;
+    let mut word = [0; 8];
+    word.copy_from_slice(bytes);
+    Some(u64::from_le_bytes(word))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+    use std::convert::TryInto;
+
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    /// Returns the words of `message` after the segment table.
+    fn words(message: &[u8]) -> Vec<u64> {
+        message[8..]
+            .chunks(8)
+            .map(|chunk| u64::from_le_bytes(chunk.try_into().unwrap()))
+            .collect()
+    }
+
+    /// Returns a key file message as `spk keygen` writes it.
+    fn key_file(signing_key: &SigningKey) -> Vec<u8> {
+        let mut message = MessageBuilder::new();
+        let root = message.init_struct(0, 0, 2);
+        let public_key = message.init_struct(root, 4, 0);
+        for (index, chunk) in signing_key.verifying_key().as_bytes().chunks(8).enumerate() {
+            message.set_word(
+                public_key + index,
+                u64::from_le_bytes(chunk.try_into().unwrap()),
+            );
+        }
+        let private_key = message.init_struct(root + 1, 8, 0);
+        for (index, chunk) in signing_key.to_keypair_bytes().chunks(8).enumerate() {
+            message.set_word(
+                private_key + index,
+                u64::from_le_bytes(chunk.try_into().unwrap()),
+            );
+        }
+        message.into_message().unwrap()
+    }
+
+    #[test]
+    fn spk_base32() {
+        assert_eq!("", base32(&[]));
+        assert_eq!("hj0h", base32(&[0b1000_0100, 0b0100_0001]));
+        assert_eq!("zw", base32(&[0xff]));
+        let app_id = app_id(&SigningKey::from_bytes(&[7; 32]));
+        assert_eq!(52, app_id.len());
+        assert!(app_id.bytes().all(|byte| BASE32_DIGITS.contains(&byte)));
+    }
+
+    #[test]
+    fn spk_archive_encoding() {
+        let contents = PackageContents {
+            symlinks: BTreeMap::from([("/l".to_string(), "t".to_string())]),
+            ..PackageContents::default()
+        };
+        let message = archive(&contents).unwrap();
+        assert_eq!([0, 0, 0, 0, 9, 0, 0, 0], message[..8]);
+        assert_eq!(
+            vec![
+                // The root pointer to `Archive`, with one pointer.
+                0x0001_0000_0000_0000,
+                // `files`, a list of structs of four words.
+                0x0000_0027_0000_0001,
+                // The tag of one struct with two words of data and two pointers.
+                0x0002_0002_0000_0004,
+                SYMLINK,
+                0,
+                // `name` and `symlink`, two bytes each, one word further on.
+                0x0000_0012_0000_0005,
+                0x0000_0012_0000_0005,
+                u64::from(b'l'),
+                u64::from(b't'),
+            ],
+            words(&message)
+        );
+    }
+
+    #[test]
+    fn spk_archive_tree() {
+        let directory = tempfile::tempdir().unwrap();
+        let script = directory.path().join("run.sh");
+        std::fs::write(&script, "#!/bin/sh\n").unwrap();
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let contents = PackageContents {
+            files: BTreeMap::from([("/opt/app/run.sh".to_string(), script)]),
+            directories: ["/opt", "/opt/app", "/opt/app/static"]
+                .iter()
+                .map(|path| path.to_string())
+                .collect(),
+            ..PackageContents::default()
+        };
+        let message = archive(&contents).unwrap();
+        let words = words(&message);
+
+        // `/opt` is the only entry of the root directory.
+        assert_eq!(0x0002_0002_0000_0004, words[2]);
+        assert_eq!(DIRECTORY, words[3]);
+        // `/opt/app` follows the name `opt` and is its only entry.
+        assert_eq!(0x0002_0002_0000_0004, words[8]);
+        assert_eq!(DIRECTORY, words[9]);
+        // `/opt/app` holds `run.sh` and `static`, in order.
+        assert_eq!(0x0002_0002_0000_0008, words[14]);
+        assert_eq!(EXECUTABLE, words[15]);
+        assert_ne!(0, words[16]);
+        assert_eq!(DIRECTORY, words[19]);
+        assert_eq!(0, words[20]);
+    }
+
+    #[test]
+    fn spk_archive_modes_and_conflicts() {
+        let directory = tempfile::tempdir().unwrap();
+        let script = directory.path().join("run.sh");
+        std::fs::write(&script, "#!/bin/sh\n").unwrap();
+        // `spk pack` only makes files which their owner may execute executable.
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o655)).unwrap();
+        let contents = PackageContents {
+            files: BTreeMap::from([("/run.sh".to_string(), script.clone())]),
+            ..PackageContents::default()
+        };
+        assert_eq!(REGULAR, words(&archive(&contents).unwrap())[3]);
+
+        let contents = PackageContents {
+            directories: BTreeSet::from(["/lib".to_string()]),
+            symlinks: BTreeMap::from([("/lib".to_string(), "usr/lib".to_string())]),
+            ..PackageContents::default()
+        };
+        assert!(matches!(archive(&contents), Err(Error::IoError(_))));
+
+        let contents = PackageContents {
+            files: BTreeMap::from([("/run.sh".to_string(), script)]),
+            symlinks: BTreeMap::from([("/run.sh/start".to_string(), "run.sh".to_string())]),
+            ..PackageContents::default()
+        };
+        assert!(matches!(archive(&contents), Err(Error::IoError(_))));
+    }
+
+    #[test]
+    fn spk_write_spk_is_signed() {
+        let signing_key = SigningKey::from_bytes(&[7; 32]);
+        let message = archive(&PackageContents::default()).unwrap();
+        let spk = write_spk(Vec::new(), &message, &signing_key).unwrap();
+        assert_eq!(MAGIC_NUMBER, spk[..8]);
+
+        let mut decompressed = Vec::new();
+        std::io::Read::read_to_end(&mut xz2::read::XzDecoder::new(&spk[8..]), &mut decompressed)
+            .unwrap();
+        let (segment, archive) = first_segment(&decompressed).unwrap();
+        assert_eq!(message, archive);
+
+        let words = words(&decompressed[..decompressed.len() - archive.len()]);
+        // The root pointer to `Signature`, with two pointers, and `publicKey`, 32 bytes one word
+        // further on.
+        assert_eq!(0x0002_0000_0000_0000, words[0]);
+        assert_eq!(0x0000_0102_0000_0005, words[1]);
+        assert_eq!(signing_key.verifying_key().as_bytes()[..], segment[24..56]);
+        // `signature`, 128 bytes after the public key.
+        assert_eq!(0x0000_0402_0000_0011, words[2]);
+        let signature = &segment[56..184];
+        let hash = Blake2b512::digest(&message);
+        assert_eq!(hash[..], signature[64..]);
+        let signature = ed25519_dalek::Signature::from_slice(&signature[..64]).unwrap();
+        signing_key
+            .verifying_key()
+            .verify_strict(&hash, &signature)
+            .unwrap();
+    }
+
+    #[test]
+    fn spk_create_spk_replaces_file() {
+        let directory = tempfile::tempdir().unwrap();
+        let spk_file = directory.path().join("app.spk");
+        let signing_key = SigningKey::from_bytes(&[7; 32]);
+        let message = archive(&PackageContents::default()).unwrap();
+        std::fs::write(&spk_file, "old package").unwrap();
+
+        create_spk(&spk_file, &message, &signing_key).unwrap();
+        assert_eq!(
+            write_spk(Vec::new(), &message, &signing_key).unwrap(),
+            std::fs::read(&spk_file).unwrap()
+        );
+        assert_eq!(1, std::fs::read_dir(directory.path()).unwrap().count());
+
+        assert!(matches!(
+            create_spk(
+                directory.path().join("missing/app.spk"),
+                &message,
+                &signing_key
+            ),
+            Err(Error::FailedToOpenFile(_, Some(_)))
+        ));
+        assert_eq!(1, std::fs::read_dir(directory.path()).unwrap().count());
+    }
+
+    #[test]
+    fn spk_read_keyring() {
+        let directory = tempfile::tempdir().unwrap();
+        let keyring = directory.path().join(KEYRING_FILE_NAME);
+        let first = SigningKey::from_bytes(&[1; 32]);
+        let second = SigningKey::from_bytes(&[2; 32]);
+        let mut bytes = key_file(&first);
+        bytes.extend(key_file(&second));
+        std::fs::write(&keyring, &bytes).unwrap();
+
+        let signing_keys = read_keyring(&keyring).unwrap();
+        assert_eq!(vec![first.clone(), second.clone()], signing_keys);
+        assert_eq!(second, signing_key(&keyring, &app_id(&second)).unwrap());
+        assert!(matches!(
+            signing_key(&keyring, &app_id(&SigningKey::from_bytes(&[3; 32]))),
+            Err(Error::NoSigningKey(_))
+        ));
+
+        std::fs::write(&keyring, &bytes[..bytes.len() - 1]).unwrap();
+        assert!(matches!(
+            read_keyring(&keyring),
+            Err(Error::InvalidKeyring(_, _))
+        ));
+    }
+}